@@ -0,0 +1,150 @@
+//! Band-limited resampling of the APU output, in the style of blip_buf.
+//!
+//! The mixed output is a sum of steps at CPU clock resolution. Instead of
+//! sampling it (which aliases badly), each change in level is added to the
+//! output as a band-limited step: a windowed sinc impulse, integrated when
+//! the samples are read out.
+
+/// Taps of each impulse, in output samples.
+const WIDTH: usize = 16;
+/// Sub-sample positions the impulse is precomputed for.
+const PHASES: usize = 64;
+/// Impulse bandwidth as a fraction of the output sample rate, just below
+/// the Nyquist limit.
+const CUTOFF: f64 = 0.45;
+
+pub struct Blip {
+    /// Output samples per input clock.
+    factor: f64,
+    /// Position of the current clock in output samples, relative to
+    /// `deltas[0]`.
+    time: f64,
+    /// Band-limited level changes, not yet integrated.
+    deltas: Vec<f32>,
+    level: f32,
+    kernel: Vec<[f32; WIDTH]>,
+}
+
+impl Blip {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        Self {
+            factor: sample_rate / clock_rate,
+            time: 0.0,
+            deltas: vec![0.0; WIDTH + 1],
+            level: 0.0,
+            kernel: (0..PHASES).map(kernel_phase).collect(),
+        }
+    }
+
+    /// Adds a change in level at the current clock.
+    pub fn add_delta(&mut self, delta: f32) {
+        let index = self.time as usize;
+        let phase = ((self.time - index as f64) * PHASES as f64) as usize;
+        if self.deltas.len() < index + WIDTH {
+            self.deltas.resize(index + WIDTH, 0.0);
+        }
+        for (slot, tap) in self.deltas[index..].iter_mut().zip(&self.kernel[phase]) {
+            *slot += delta * tap;
+        }
+    }
+
+    /// Advances one input clock.
+    pub fn clock(&mut self) {
+        self.time += self.factor;
+    }
+
+    /// Output samples that no later delta can change anymore.
+    pub fn samples_available(&self) -> usize {
+        self.time as usize
+    }
+
+    /// Moves the completed samples to `out`.
+    pub fn read_samples(&mut self, mut out: impl FnMut(f32)) {
+        let count = self.samples_available();
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+        for delta in self.deltas.drain(..count) {
+            self.level += delta;
+            out(self.level);
+        }
+        self.time -= count as f64;
+    }
+}
+
+/// Impulse for a step `phase / PHASES` of a sample after a sample boundary,
+/// delayed by half the kernel width. Normalized so a step of 1 adds 1.
+fn kernel_phase(phase: usize) -> [f32; WIDTH] {
+    let offset = phase as f64 / PHASES as f64;
+    let half = WIDTH as f64 / 2.0;
+    let mut taps = [0.0; WIDTH];
+    for (k, tap) in taps.iter_mut().enumerate() {
+        let t = k as f64 - half - offset;
+        let x = 2.0 * CUTOFF * t;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+        };
+        // Blackman window over [-half, half]
+        let w = (t + half) / WIDTH as f64;
+        let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * w).cos()
+            + 0.08 * (4.0 * std::f64::consts::PI * w).cos();
+        *tap = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    taps.map(|tap| (tap / sum) as f32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_all(blip: &mut Blip) -> Vec<f32> {
+        let mut samples = Vec::new();
+        blip.read_samples(|sample| samples.push(sample));
+        samples
+    }
+
+    #[test]
+    fn test_step_settles() {
+        let mut blip = Blip::new(1_789_773.0, 44_100.0);
+        for _ in 0..1000 {
+            blip.clock();
+        }
+        blip.add_delta(0.5);
+        for _ in 0..2000 {
+            blip.clock();
+        }
+        let samples = read_all(&mut blip);
+        assert_eq!(samples.len(), (3000.0 * 44_100.0 / 1_789_773.0) as usize);
+        assert_eq!(samples[0], 0.0);
+        assert!((samples.last().unwrap() - 0.5).abs() < 1e-4);
+
+        // Reading again continues where the last read stopped
+        for _ in 0..41 {
+            blip.clock();
+        }
+        assert_eq!(read_all(&mut blip).len(), 1);
+    }
+
+    #[test]
+    fn test_no_aliasing() {
+        // A square wave far above the output Nyquist frequency averages out
+        // instead of folding down into audible tones
+        let clock_rate = 1_789_773.0;
+        let mut blip = Blip::new(clock_rate, 44_100.0);
+        let mut high = false;
+        for cycle in 0..200_000 {
+            if cycle % 20 == 0 {
+                high = !high;
+                blip.add_delta(if high { 1.0 } else { -1.0 });
+            }
+            blip.clock();
+        }
+        let samples = read_all(&mut blip);
+        for sample in &samples[100..] {
+            assert!((sample - 0.5).abs() < 0.05, "{}", sample);
+        }
+    }
+}
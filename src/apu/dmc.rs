@@ -0,0 +1,221 @@
+use crate::region::Region;
+
+/// Output unit periods in CPU cycles, selected by the low four bits of $4010.
+const RATE_TABLE_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const RATE_TABLE_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Delta modulation channel ($4010-$4013): plays 1-bit delta-encoded
+/// samples that it fetches from $8000-$FFFF by DMA, one byte at a time.
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    /// 7-bit output level, moved up or down by 2 for each sample bit.
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    /// Memory reader state.
+    address: u16,
+    bytes_remaining: u16,
+    /// Byte fetched by the last DMA, waiting for the output unit.
+    buffer: Option<u8>,
+    /// Output unit state.
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub(super) irq: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Self {
+        let mut dmc = Self {
+            rates: &RATE_TABLE_NTSC,
+            irq_enabled: false,
+            looping: false,
+            timer_period: 0,
+            timer: 0,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        };
+        dmc.set_region(region);
+        dmc.timer_period = dmc.rates[0] - 1;
+        dmc
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.rates = match region {
+            Region::Ntsc => &RATE_TABLE_NTSC,
+            Region::Pal | Region::Dendy => &RATE_TABLE_PAL,
+        };
+    }
+
+    /// Register write; `register` is the address offset 0-3.
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.timer_period = self.rates[(data & 0x0F) as usize] - 1;
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | ((data as u16) << 6),
+            _ => self.sample_length = ((data as u16) << 4) | 1,
+        }
+    }
+
+    /// $4015 enable bit: stops the sample, or starts it if it has finished.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Address of the sample byte the memory reader wants fetched, if its
+    /// buffer is empty and the sample has bytes left.
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.address)
+        } else {
+            None
+        }
+    }
+
+    /// Completes a DMA fetch started for `dma_address`.
+    pub fn fill_buffer(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.address = self.address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current output level, 0-127.
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn clock_bits(dmc: &mut Dmc, bits: usize) {
+        for _ in 0..bits * (dmc.timer_period as usize + 1) {
+            dmc.clock_timer();
+        }
+    }
+
+    #[test]
+    fn test_sample_playback() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(0, 0x8F); // IRQ, fastest rate
+        dmc.write(1, 0x40);
+        dmc.write(2, 0xFF); // $FFC0
+        dmc.write(3, 0x04); // 65 bytes
+        assert_eq!(dmc.dma_address(), None);
+        dmc.set_enabled(true);
+        assert_eq!(dmc.dma_address(), Some(0xFFC0));
+        dmc.fill_buffer(0b0000_1111);
+        assert_eq!(dmc.dma_address(), None);
+
+        // The buffer is picked up at the end of the current (silent) byte
+        clock_bits(&mut dmc, 8);
+        assert_eq!(dmc.output(), 0x40);
+        assert_eq!(dmc.dma_address(), Some(0xFFC1));
+        clock_bits(&mut dmc, 4);
+        assert_eq!(dmc.output(), 0x48);
+        clock_bits(&mut dmc, 4);
+        assert_eq!(dmc.output(), 0x40);
+
+        // The address wraps to $8000 and the last byte raises the IRQ
+        for _ in 0..63 {
+            dmc.fill_buffer(0);
+        }
+        assert_eq!(dmc.address, 0x8000);
+        assert!(!dmc.irq);
+        dmc.fill_buffer(0);
+        assert!(dmc.irq);
+        assert!(!dmc.active());
+        dmc.write(0, 0x0F);
+        assert!(!dmc.irq);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut dmc = Dmc::new(Region::Ntsc);
+        dmc.write(0, 0xC0); // IRQ and loop
+        dmc.write(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+        dmc.fill_buffer(0);
+        assert!(dmc.active());
+        assert!(!dmc.irq);
+        assert_eq!(dmc.dma_address(), None);
+        dmc.set_enabled(false);
+        assert!(!dmc.active());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::region::Region;
+
+/// CPU cycles after a reset of the frame counter at which each step of the
+/// 4-step and 5-step sequences happens. The last entry is where the
+/// sequence wraps.
+type Steps = [[u16; 6]; 2];
+
+const STEPS_NTSC: Steps = [
+    [7457, 14913, 22371, 29828, 29829, 29830],
+    [7457, 14913, 22371, 29829, 37281, 37282],
+];
+const STEPS_PAL: Steps = [
+    [8313, 16627, 24939, 33252, 33253, 33254],
+    [8313, 16627, 24939, 33253, 41565, 41566],
+];
+
+/// Frame sequencer: divides the CPU clock into the quarter and half frame
+/// clocks that drive envelopes, sweeps and length counters, and raises the
+/// frame IRQ at the end of the 4-step sequence.
+pub(super) struct FrameCounter {
+    steps: &'static Steps,
+    five_step: bool,
+    irq_inhibit: bool,
+    pub(super) irq: bool,
+    cycle: u16,
+    /// CPU cycles until a $4017 write restarts the sequence.
+    reset_delay: u8,
+}
+
+/// Clocks produced by one CPU cycle of the frame counter.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct FrameClock {
+    pub quarter: bool,
+    pub half: bool,
+}
+
+impl FrameClock {
+    const QUARTER: FrameClock = FrameClock {
+        quarter: true,
+        half: false,
+    };
+    const HALF: FrameClock = FrameClock {
+        quarter: true,
+        half: true,
+    };
+}
+
+impl FrameCounter {
+    pub(super) fn new(region: Region) -> Self {
+        let mut counter = Self {
+            steps: &STEPS_NTSC,
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            reset_delay: 0,
+        };
+        counter.set_region(region);
+        counter
+    }
+
+    pub(super) fn set_region(&mut self, region: Region) {
+        // Dendy keeps the NTSC frame counter
+        self.steps = match region {
+            Region::Ntsc | Region::Dendy => &STEPS_NTSC,
+            Region::Pal => &STEPS_PAL,
+        };
+    }
+
+    /// $4017 write: MI-- ----. The sequence restarts 3 CPU cycles later if
+    /// the write lands on an APU cycle, 4 if it lands between two.
+    pub(super) fn write(&mut self, data: u8, apu_cycle: bool) {
+        self.five_step = data & 0x80 != 0;
+        self.irq_inhibit = data & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.reset_delay = if apu_cycle { 3 } else { 4 };
+    }
+
+    /// Advances one CPU cycle.
+    pub(super) fn tick(&mut self) -> FrameClock {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.cycle = 0;
+                // Entering 5-step mode clocks everything right away
+                return if self.five_step {
+                    FrameClock::HALF
+                } else {
+                    FrameClock::default()
+                };
+            }
+        }
+
+        self.cycle += 1;
+        let steps = &self.steps[self.five_step as usize];
+        let Some(step) = steps.iter().position(|&cycle| cycle == self.cycle) else {
+            return FrameClock::default();
+        };
+        if !self.five_step && step >= 3 && !self.irq_inhibit {
+            self.irq = true;
+        }
+        if step == 5 {
+            self.cycle = 0;
+        }
+        match step {
+            0 | 2 => FrameClock::QUARTER,
+            1 | 4 => FrameClock::HALF,
+            _ => FrameClock::default(),
+        }
+    }
+}
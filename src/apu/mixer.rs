@@ -0,0 +1,135 @@
+/// Lookup-table approximation of the APU's non-linear DAC, from the sum of
+/// the two pulse levels and the weighted triangle/noise/DMC levels.
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Self {
+            pulse_table,
+            tnd_table,
+        }
+    }
+
+    /// Output level between 0 and about 1 for the given channel levels, in
+    /// `CHANNEL_NAMES` order (pulses, triangle and noise 0-15, DMC 0-127).
+    pub fn mix(&self, levels: [u8; 5]) -> f32 {
+        let [pulse1, pulse2, triangle, noise, dmc] = levels;
+        let pulse = self.pulse_table[(pulse1 + pulse2) as usize];
+        let tnd = 3 * triangle as usize + 2 * noise as usize + dmc as usize;
+        pulse + self.tnd_table[tnd]
+    }
+}
+
+/// First-order filter run at the output sample rate.
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Self {
+            kind,
+            alpha,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+            FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+        };
+        self.prev_input = input;
+        self.prev_output = output;
+        output
+    }
+}
+
+/// The filters between the APU and the audio output of a stock NES: two
+/// high-pass filters at 90 Hz and 440 Hz, and a 14 kHz low-pass.
+pub struct OutputFilters([Filter; 3]);
+
+impl OutputFilters {
+    pub fn new(sample_rate: f32) -> Self {
+        Self([
+            Filter::new(FilterKind::HighPass, 90.0, sample_rate),
+            Filter::new(FilterKind::HighPass, 440.0, sample_rate),
+            Filter::new(FilterKind::LowPass, 14_000.0, sample_rate),
+        ])
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.0
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix_levels() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.mix([0, 0, 0, 0, 0]), 0.0);
+        // Reference values from the NESdev formulas
+        assert!((mixer.mix([15, 15, 0, 0, 0]) - 0.2575).abs() < 1e-3);
+        assert!((mixer.mix([0, 0, 15, 15, 127]) - 0.7425).abs() < 1e-3);
+        // Non-linear: two pulses are quieter than twice one
+        assert!(mixer.mix([15, 15, 0, 0, 0]) < 2.0 * mixer.mix([15, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_filters() {
+        // The high-pass filters remove a constant offset
+        let mut filters = OutputFilters::new(44_100.0);
+        let mut output = 1.0;
+        for _ in 0..4410 {
+            output = filters.process(0.5);
+        }
+        assert!(output.abs() < 1e-3);
+
+        // The low-pass filter passes slow changes and smooths steps
+        let mut low_pass = Filter::new(FilterKind::LowPass, 14_000.0, 44_100.0);
+        let first = low_pass.process(1.0);
+        assert!(first > 0.5 && first < 1.0);
+        for _ in 0..10 {
+            low_pass.process(1.0);
+        }
+        assert!((low_pass.process(1.0) - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,435 @@
+mod blip;
+mod dmc;
+mod frame_counter;
+mod mixer;
+mod noise;
+mod output;
+mod pulse;
+mod ring_buffer;
+mod triangle;
+mod units;
+
+use crate::region::Region;
+use frame_counter::FrameCounter;
+use mixer::Mixer;
+use output::Output;
+use std::ops::RangeInclusive;
+
+pub use dmc::Dmc;
+pub use noise::Noise;
+pub use pulse::{Pulse, PulseChannel};
+pub use triangle::Triangle;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+/// Output sample rates `set_sample_rate` accepts, in Hz.
+pub const SAMPLE_RATES: RangeInclusive<u32> = 8_000..=192_000;
+/// Names of the channels, in the order of `APU::drain_stems`.
+pub const CHANNEL_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+/// Resampled output is filtered and buffered in chunks of this many samples.
+const SAMPLE_CHUNK: usize = 32;
+
+/// Audio processing unit: the five channels, the mixer, and resampling of
+/// the mixed output to the host sample rate.
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    /// Channel timers run at half the CPU clock, on odd CPU cycles.
+    odd_cycle: bool,
+    region: Region,
+    sample_rate: u32,
+    mixer: Mixer,
+    output: Output,
+    /// Each channel mixed on its own, when recording stems.
+    stems: Option<Box<[Output; 5]>>,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(Region::default()),
+            dmc: Dmc::new(Region::default()),
+            frame_counter: FrameCounter::new(Region::default()),
+            odd_cycle: false,
+            region: Region::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            mixer: Mixer::new(),
+            output: Output::new(Region::default().cpu_clock_rate(), DEFAULT_SAMPLE_RATE, 0.0),
+            stems: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_counter.set_region(region);
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.region = region;
+        self.reset_output();
+    }
+
+    /// Output sample rate in Hz, typically 44100 or 48000. Panics if it is
+    /// outside `SAMPLE_RATES`.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        assert!(
+            SAMPLE_RATES.contains(&sample_rate),
+            "sample rate {sample_rate} Hz is outside {SAMPLE_RATES:?}"
+        );
+        self.sample_rate = sample_rate;
+        self.reset_output();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Also produces each channel's output on its own, for `drain_stems`.
+    pub fn set_stems_enabled(&mut self, enabled: bool) {
+        self.stems = enabled.then(|| Box::new(self.new_stems()));
+    }
+
+    fn new_stems(&self) -> [Output; 5] {
+        let levels = self.channel_levels();
+        std::array::from_fn(|channel| {
+            let level = self.mixer.mix(solo(levels, channel));
+            Output::new(self.region.cpu_clock_rate(), self.sample_rate, level)
+        })
+    }
+
+    /// Restarts resampling for a new clock or sample rate. Buffered samples
+    /// are dropped.
+    fn reset_output(&mut self) {
+        let level = self.output.level();
+        self.output = Output::new(self.region.cpu_clock_rate(), self.sample_rate, level);
+        if self.stems.is_some() {
+            self.stems = Some(Box::new(self.new_stems()));
+        }
+    }
+
+    fn channel_levels(&self) -> [u8; 5] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ]
+    }
+
+    /// Advances the APU by one CPU cycle.
+    pub fn tick(&mut self) {
+        let clock = self.frame_counter.tick();
+        if clock.quarter {
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if clock.half {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        let levels = self.channel_levels();
+        self.output.clock(self.mixer.mix(levels));
+        if let Some(stems) = &mut self.stems {
+            for (channel, stem) in stems.iter_mut().enumerate() {
+                stem.clock(self.mixer.mix(solo(levels, channel)));
+            }
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr & 0x03, data),
+            0x4004..=0x4007 => self.pulse2.write(addr & 0x03, data),
+            0x4008..=0x400B => self.triangle.write(addr & 0x03, data),
+            0x400C..=0x400F => self.noise.write(addr & 0x03, data),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0x01 != 0);
+                self.pulse2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+                self.dmc.irq = false;
+            }
+            0x4017 => self.frame_counter.write(data, self.odd_cycle),
+            _ => {}
+        }
+    }
+
+    /// $4015 read: IF-D NT21. Length counter and DMC activity, and the two
+    /// IRQ flags. Clears the frame IRQ flag.
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.pulse1.length.active() as u8)
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.irq as u8) << 6
+            | (self.dmc.irq as u8) << 7;
+        self.frame_counter.irq = false;
+        status
+    }
+
+    pub fn pulse1(&self) -> &Pulse {
+        &self.pulse1
+    }
+
+    pub fn pulse2(&self) -> &Pulse {
+        &self.pulse2
+    }
+
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    pub fn noise(&self) -> &Noise {
+        &self.noise
+    }
+
+    pub fn dmc(&self) -> &Dmc {
+        &self.dmc
+    }
+
+    /// The 4-step frame sequence ended with the frame IRQ enabled.
+    pub fn frame_irq(&self) -> bool {
+        self.frame_counter.irq
+    }
+
+    /// The DMC finished a sample with its IRQ enabled.
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.irq
+    }
+
+    /// Address of the sample byte the DMC wants fetched by DMA, if any.
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    /// Delivers the byte fetched for `dmc_dma_address`.
+    pub fn dmc_dma_fill(&mut self, data: u8) {
+        self.dmc.fill_buffer(data);
+    }
+
+    /// Samples waiting to be drained, for frontends that pace themselves by
+    /// audio latency.
+    pub fn buffered_samples(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Takes the samples produced since the last call.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        self.output.drain()
+    }
+
+    /// Takes each channel's samples produced since the last call, in
+    /// `CHANNEL_NAMES` order. `None` unless stems are enabled.
+    pub fn drain_stems(&mut self) -> Option<[Vec<f32>; 5]> {
+        let stems = self.stems.as_mut()?;
+        Some(std::array::from_fn(|channel| stems[channel].drain()))
+    }
+}
+
+/// `levels` with every channel but `channel` silenced.
+fn solo(levels: [u8; 5], channel: usize) -> [u8; 5] {
+    let mut solo = [0; 5];
+    solo[channel] = levels[channel];
+    solo
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_timing() {
+        // The second half frame ends the 4-step sequence
+        for (region, second_half_frame) in [(Region::Ntsc, 29829), (Region::Pal, 33253)] {
+            let mut apu = APU::new();
+            apu.set_region(region);
+            apu.cpu_write(0x4015, 0x01);
+            apu.cpu_write(0x4000, 0x1F); // constant volume 15
+            apu.cpu_write(0x4002, 0x40);
+            apu.cpu_write(0x4003, 0x18); // 2 half frames
+            apu.cpu_write(0x4007, 0x18); // ignored, pulse 2 is disabled
+
+            for _ in 0..second_half_frame - 1 {
+                apu.tick();
+            }
+            assert!(apu.pulse1.length.active());
+            assert!(!apu.pulse2.length.active());
+            apu.tick();
+            assert!(!apu.pulse1.length.active());
+
+            // Disabling a channel clears its length counter
+            apu.cpu_write(0x4003, 0x18);
+            assert!(apu.pulse1.length.active());
+            apu.cpu_write(0x4015, 0x00);
+            assert!(!apu.pulse1.length.active());
+        }
+    }
+
+    fn tick(apu: &mut APU, cycles: usize) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = APU::new();
+        tick(&mut apu, 29827);
+        assert!(!apu.frame_irq());
+        tick(&mut apu, 1);
+        assert!(apu.frame_irq());
+        assert_eq!(apu.read_status(), 0x40);
+        assert_eq!(apu.read_status(), 0x00);
+
+        // The flag is set again on the next two cycles
+        tick(&mut apu, 2);
+        assert!(apu.frame_irq());
+        apu.read_status();
+        tick(&mut apu, 1);
+        assert!(!apu.frame_irq());
+
+        // The inhibit bit clears the flag and keeps it clear
+        tick(&mut apu, 29830);
+        assert!(apu.frame_irq());
+        apu.cpu_write(0x4017, 0x40);
+        assert!(!apu.frame_irq());
+        tick(&mut apu, 2 * 29830);
+        assert!(!apu.frame_irq());
+    }
+
+    #[test]
+    fn test_frame_counter_reset_delay() {
+        for (apu_cycle, delay) in [(true, 3), (false, 4)] {
+            let mut apu = APU::new();
+            if apu.odd_cycle != apu_cycle {
+                apu.tick();
+            }
+            apu.cpu_write(0x4017, 0x00);
+            tick(&mut apu, delay + 29827);
+            assert!(!apu.frame_irq());
+            tick(&mut apu, 1);
+            assert!(apu.frame_irq());
+        }
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut apu = APU::new();
+        apu.cpu_write(0x4015, 0x01);
+        apu.cpu_write(0x4003, 0x18); // 2 half frames
+
+        // Switching to 5-step mode clocks the length counter once right
+        // away, so the first half frame of the sequence silences it
+        apu.cpu_write(0x4017, 0x80);
+        tick(&mut apu, 4 + 14912);
+        assert_eq!(apu.read_status(), 0x01);
+        tick(&mut apu, 1);
+        assert_eq!(apu.read_status(), 0x00);
+
+        // No frame IRQ in 5-step mode
+        tick(&mut apu, 3 * 37282);
+        assert!(!apu.frame_irq());
+    }
+
+    #[test]
+    fn test_status() {
+        let mut apu = APU::new();
+        apu.cpu_write(0x4015, 0x1F);
+        for addr in [0x4003, 0x4007, 0x400B, 0x400F] {
+            apu.cpu_write(addr, 0x08);
+        }
+        apu.cpu_write(0x4010, 0x8F);
+        assert_eq!(apu.read_status(), 0x1F);
+
+        // The DMC IRQ survives status reads, but not $4015 writes
+        apu.dmc_dma_fill(0);
+        assert_eq!(apu.read_status(), 0x8F);
+        assert_eq!(apu.read_status(), 0x8F);
+        apu.cpu_write(0x4015, 0x00);
+        assert_eq!(apu.read_status(), 0x00);
+    }
+
+    #[test]
+    fn test_sample_output() {
+        for sample_rate in [44_100, 48_000] {
+            let mut apu = APU::new();
+            apu.set_sample_rate(sample_rate);
+            apu.cpu_write(0x4015, 0x01);
+            apu.cpu_write(0x4000, 0xBF); // 50% duty, constant volume 15
+            apu.cpu_write(0x4002, 253); // 440 Hz
+            apu.cpu_write(0x4003, 0x00);
+
+            let cycles = 1_789_773 / 4;
+            tick(&mut apu, cycles);
+            let samples = apu.drain_samples();
+            assert!(samples.len().abs_diff(sample_rate as usize / 4) <= 1);
+            assert!(apu.drain_samples().is_empty());
+
+            // Filtered output swings around zero at the pulse frequency
+            let mut rising = 0usize;
+            let mut high = false;
+            for &sample in &samples {
+                if !high && sample > 0.05 {
+                    rising += 1;
+                    high = true;
+                } else if high && sample < -0.05 {
+                    high = false;
+                }
+            }
+            assert!(rising.abs_diff(110) <= 1, "{}", rising);
+            let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            assert!(peak > 0.05 && peak < 0.3, "{}", peak);
+        }
+    }
+
+    #[test]
+    fn test_stems() {
+        let mut apu = APU::new();
+        assert!(apu.drain_stems().is_none());
+        apu.set_stems_enabled(true);
+        apu.cpu_write(0x4015, 0x04);
+        apu.cpu_write(0x4008, 0xFF); // triangle, linear counter held
+        apu.cpu_write(0x400A, 0x7E);
+        apu.cpu_write(0x400B, 0x00);
+        tick(&mut apu, 1_789_773 / 10);
+
+        let mix = apu.drain_samples();
+        let stems = apu.drain_stems().unwrap();
+        let loud = |samples: &[f32]| samples.iter().any(|s| s.abs() > 0.01);
+        assert!(loud(&mix));
+        for (name, stem) in CHANNEL_NAMES.iter().zip(&stems) {
+            assert_eq!(stem.len(), mix.len());
+            assert_eq!(loud(stem), *name == "triangle", "{}", name);
+        }
+        // With one channel playing, its stem is the mix
+        assert_eq!(stems[2], mix);
+    }
+}
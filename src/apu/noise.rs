@@ -0,0 +1,139 @@
+use super::units::{Envelope, LengthCounter};
+use crate::region::Region;
+
+/// Timer periods in CPU cycles, selected by the low four bits of $400E.
+const PERIOD_TABLE_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PERIOD_TABLE_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// Noise channel ($400C-$400F): a 15-bit linear feedback shift register
+/// clocked by a timer, gated through an envelope.
+pub struct Noise {
+    periods: &'static [u16; 16],
+    /// Short mode taps bit 6 instead of bit 1, for a 93-step metallic tone.
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift: u16,
+    envelope: Envelope,
+    pub(super) length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Self {
+        let mut noise = Self {
+            periods: &PERIOD_TABLE_NTSC,
+            short_mode: false,
+            timer_period: 0,
+            timer: 0,
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        };
+        noise.set_region(region);
+        noise.timer_period = noise.periods[0] - 1;
+        noise
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = match region {
+            Region::Ntsc => &PERIOD_TABLE_NTSC,
+            Region::Pal | Region::Dendy => &PERIOD_TABLE_PAL,
+        };
+    }
+
+    /// Register write; `register` is the address offset 0-3.
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write_control(data);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.timer_period = self.periods[(data & 0x0F) as usize] - 1;
+            }
+            _ => {
+                self.length.load(data);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current output level, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Timer clocks until the shift register returns to its seed.
+    fn lfsr_period(short_mode: bool) -> usize {
+        let mut noise = Noise::new(Region::Ntsc);
+        noise.write(2, if short_mode { 0x80 } else { 0x00 });
+        let seed = noise.shift;
+        let mut period = 0;
+        loop {
+            for _ in 0..=noise.timer_period {
+                noise.clock_timer();
+            }
+            period += 1;
+            if noise.shift == seed {
+                return period;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_modes() {
+        assert_eq!(lfsr_period(false), 32767);
+        assert_eq!(lfsr_period(true), 93);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise = Noise::new(Region::Pal);
+        noise.length.set_enabled(true);
+        noise.write(0, 0x1A); // constant volume 10
+        noise.write(2, 0x0F);
+        assert_eq!(noise.timer_period, 3777);
+        noise.write(3, 0x08);
+        // The seed has bit 0 set, which mutes the channel
+        assert_eq!(noise.output(), 0);
+        for _ in 0..=noise.timer_period {
+            noise.clock_timer();
+        }
+        assert_eq!(noise.output(), 10);
+    }
+}
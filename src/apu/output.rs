@@ -0,0 +1,60 @@
+use super::SAMPLE_CHUNK;
+use super::blip::Blip;
+use super::mixer::OutputFilters;
+use super::ring_buffer::RingBuffer;
+
+/// Turns the per-cycle output of the mixer into filtered samples at the
+/// host rate, buffered until the frontend drains them.
+pub(super) struct Output {
+    /// Level as of the last clock.
+    level: f32,
+    blip: Blip,
+    filters: OutputFilters,
+    samples: RingBuffer,
+}
+
+impl Output {
+    /// Starts resampling at `level`. Half a second of samples is buffered.
+    pub(super) fn new(clock_rate: f64, sample_rate: u32, level: f32) -> Self {
+        let mut blip = Blip::new(clock_rate, sample_rate as f64);
+        blip.add_delta(level);
+        Self {
+            level,
+            blip,
+            filters: OutputFilters::new(sample_rate as f32),
+            samples: RingBuffer::new(sample_rate as usize / 2),
+        }
+    }
+
+    pub(super) fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Records `level` for the current clock and advances to the next.
+    pub(super) fn clock(&mut self, level: f32) {
+        if level != self.level {
+            self.blip.add_delta(level - self.level);
+            self.level = level;
+        }
+        self.blip.clock();
+        if self.blip.samples_available() >= SAMPLE_CHUNK {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let filters = &mut self.filters;
+        let samples = &mut self.samples;
+        self.blip
+            .read_samples(|sample| samples.push(filters.process(sample)));
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub(super) fn drain(&mut self) -> Vec<f32> {
+        self.flush();
+        self.samples.drain()
+    }
+}
@@ -0,0 +1,241 @@
+use super::units::{Envelope, LengthCounter};
+
+/// Output of the 8-step sequencer for each duty setting (12.5%, 25%, 50%
+/// and 25% negated). The sequencer counts down from step 0.
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Which of the two otherwise identical pulse channels this is. They differ
+/// only in how the sweep unit negates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PulseChannel {
+    /// Pulse 1 negates with ones' complement, subtracting one more.
+    One,
+    /// Pulse 2 negates with two's complement.
+    Two,
+}
+
+/// Sweep unit: periodically adds or subtracts a shifted copy of the timer
+/// period to bend the pitch.
+#[derive(Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+/// Square wave channel ($4000-$4003 and $4004-$4007).
+pub struct Pulse {
+    channel: PulseChannel,
+    duty: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    sweep: Sweep,
+    pub(super) length: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Self {
+            channel,
+            duty: 0,
+            step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    /// Register write; `register` is the address offset 0-3.
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write_control(data);
+            }
+            1 => {
+                self.sweep.enabled = data & 0x80 != 0;
+                self.sweep.period = (data >> 4) & 0x07;
+                self.sweep.negate = data & 0x08 != 0;
+                self.sweep.shift = data & 0x07;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    /// Clocked every APU cycle (every other CPU cycle).
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = self.step.wrapping_sub(1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        let sweep = &self.sweep;
+        if sweep.divider == 0 && sweep.enabled && sweep.shift != 0 && !self.muted() {
+            self.timer_period = self.sweep_target();
+        }
+        let sweep = &mut self.sweep;
+        if sweep.divider == 0 || sweep.reload {
+            sweep.divider = sweep.period;
+            sweep.reload = false;
+        } else {
+            sweep.divider -= 1;
+        }
+    }
+
+    /// Period the sweep unit would set next. Computed continuously, since it
+    /// mutes the channel even when the sweep is disabled.
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.timer_period + change
+        } else {
+            match self.channel {
+                PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+                PulseChannel::Two => self.timer_period.saturating_sub(change),
+            }
+        }
+    }
+
+    /// Periods below 8 and sweep targets past $7FF silence the channel.
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    /// Current output level, 0-15.
+    pub fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(channel: PulseChannel, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0xBF); // 50% duty, halt, constant volume 15
+        pulse.write(2, (period & 0xFF) as u8);
+        pulse.write(3, (period >> 8) as u8);
+        pulse
+    }
+
+    /// Output over one full sequence, sampled once per step.
+    fn waveform(pulse: &mut Pulse) -> Vec<u8> {
+        (0..8)
+            .map(|_| {
+                let output = pulse.output();
+                for _ in 0..=pulse.timer_period {
+                    pulse.clock_timer();
+                }
+                output
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duty_sequence() {
+        let mut pulse = playing(PulseChannel::One, 0x100);
+        assert_eq!(waveform(&mut pulse), [0, 0, 0, 0, 15, 15, 15, 15]);
+        pulse.write(0, 0x3F);
+        assert_eq!(
+            waveform(&mut pulse),
+            [0, 0, 0, 0, 0, 0, 0, 1].map(|v| v * 15)
+        );
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        // Pulse 1 subtracts one more than pulse 2
+        for (channel, period) in [(PulseChannel::One, 0x0BF), (PulseChannel::Two, 0x0C0)] {
+            let mut pulse = playing(channel, 0x100);
+            pulse.write(1, 0x8A); // enabled, period 0, negate, shift 2
+            pulse.clock_half_frame();
+            assert_eq!(pulse.timer_period, period);
+        }
+
+        let mut pulse = playing(PulseChannel::One, 0x100);
+        pulse.write(1, 0xA1); // enabled, period 2, shift 1
+        let periods: Vec<u16> = (0..4)
+            .map(|_| {
+                pulse.clock_half_frame();
+                pulse.timer_period
+            })
+            .collect();
+        assert_eq!(periods, [0x180, 0x180, 0x180, 0x240]);
+    }
+
+    #[test]
+    fn test_sweep_muting() {
+        let mut pulse = playing(PulseChannel::Two, 0x007);
+        assert_eq!(waveform(&mut pulse), [0; 8]);
+
+        // An overflowing target mutes even with the sweep disabled
+        let mut pulse = playing(PulseChannel::Two, 0x600);
+        pulse.write(1, 0x01);
+        assert!(waveform(&mut pulse).iter().all(|&v| v == 0));
+        pulse.write(1, 0x02);
+        assert!(waveform(&mut pulse).contains(&15));
+    }
+
+    #[test]
+    fn test_envelope_and_length() {
+        let mut pulse = playing(PulseChannel::One, 0x100);
+        pulse.write(0, 0x81); // decaying volume, divider period 1
+        pulse.write(3, 0x08); // length index 1: 254 half frames
+        pulse.clock_quarter_frame();
+        assert_eq!(pulse.envelope.output(), 15);
+        for _ in 0..4 {
+            pulse.clock_quarter_frame();
+        }
+        assert_eq!(pulse.envelope.output(), 13);
+        for _ in 0..30 {
+            pulse.clock_quarter_frame();
+        }
+        assert_eq!(pulse.envelope.output(), 0);
+
+        pulse.write(3, 0x18); // length index 3: 2 half frames
+        pulse.clock_half_frame();
+        assert!(pulse.length.active());
+        pulse.clock_half_frame();
+        assert!(!pulse.length.active());
+        assert_eq!(waveform(&mut pulse), [0; 8]);
+    }
+}
@@ -0,0 +1,65 @@
+/// Fixed-capacity FIFO of output samples between the emulator and the
+/// audio frontend. When the frontend falls behind, the oldest samples are
+/// overwritten.
+pub struct RingBuffer {
+    data: Vec<f32>,
+    /// Index of the oldest sample.
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![0.0; capacity],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let capacity = self.data.len();
+        self.data[(self.start + self.len) % capacity] = sample;
+        if self.len == capacity {
+            self.start = (self.start + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Removes and returns all buffered samples, oldest first.
+    pub fn drain(&mut self) -> Vec<f32> {
+        let capacity = self.data.len();
+        let samples = (0..self.len)
+            .map(|i| self.data[(self.start + i) % capacity])
+            .collect();
+        self.start = 0;
+        self.len = 0;
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overwrites_oldest() {
+        let mut ring = RingBuffer::new(4);
+        for sample in 0..3 {
+            ring.push(sample as f32);
+        }
+        assert_eq!(ring.drain(), [0.0, 1.0, 2.0]);
+        assert_eq!(ring.len(), 0);
+
+        for sample in 0..6 {
+            ring.push(sample as f32);
+        }
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.drain(), [2.0, 3.0, 4.0, 5.0]);
+    }
+}
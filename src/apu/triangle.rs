@@ -0,0 +1,126 @@
+use super::units::LengthCounter;
+
+/// The 32-step triangle waveform: 15 down to 0, then back up.
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Triangle channel ($4008-$400B). Has no volume control; a linear counter
+/// gates it in addition to the length counter.
+#[derive(Default)]
+pub struct Triangle {
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    /// Also the length counter halt flag.
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    pub(super) length: LengthCounter,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register write; `register` is the address offset 0-3.
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle. The sequencer only moves while both counters
+    /// are non-zero, so a silenced triangle holds its last level.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length.active() {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Quarter-frame clock of the linear counter.
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Current output level, 0-15.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Clocks the timer through `steps` sequencer periods.
+    fn run_steps(triangle: &mut Triangle, steps: usize) {
+        for _ in 0..steps * (triangle.timer_period as usize + 1) {
+            triangle.clock_timer();
+        }
+    }
+
+    #[test]
+    fn test_sequence_and_linear_counter() {
+        let mut triangle = Triangle::new();
+        triangle.length.set_enabled(true);
+        triangle.write(0, 0x02); // linear counter 2, control clear
+        triangle.write(2, 0x10);
+        triangle.write(3, 0x08);
+
+        // Silent until the linear counter is loaded on a quarter frame
+        run_steps(&mut triangle, 4);
+        assert_eq!(triangle.output(), 15);
+        triangle.clock_quarter_frame();
+        run_steps(&mut triangle, 4);
+        assert_eq!(triangle.output(), 11);
+        run_steps(&mut triangle, 16);
+        assert_eq!(triangle.output(), 4);
+
+        // The counter runs out after two more quarter frames and the
+        // sequencer holds its level
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        run_steps(&mut triangle, 5);
+        assert_eq!(triangle.output(), 4);
+
+        // With the control flag set the counter reloads every quarter frame
+        triangle.write(0, 0x81);
+        triangle.write(3, 0x08);
+        for _ in 0..10 {
+            triangle.clock_quarter_frame();
+        }
+        run_steps(&mut triangle, 1);
+        assert_eq!(triangle.output(), 5);
+    }
+}
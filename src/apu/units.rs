@@ -0,0 +1,97 @@
+//! Building blocks shared by several channels.
+
+/// Length counter load values, indexed by the 5-bit value written to the
+/// channel's length register.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel after a programmed number of half frames.
+#[derive(Default)]
+pub(super) struct LengthCounter {
+    counter: u8,
+    enabled: bool,
+    pub(super) halt: bool,
+}
+
+impl LengthCounter {
+    /// $4015 channel enable. Disabling clears the counter right away.
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Loads the counter from the upper five bits of a length register
+    /// write. Ignored while the channel is disabled.
+    pub(super) fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+
+    pub(super) fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub(super) fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+/// Volume of the pulse and noise channels: either a constant, or a
+/// sawtooth decaying from 15 once per divider period, optionally looping.
+#[derive(Default)]
+pub(super) struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    /// Constant volume, or the divider period.
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Low six bits of the channel's first register: --LC VVVV.
+    pub(super) fn write_control(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    /// Restarts the decay on the next quarter frame.
+    pub(super) fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Quarter-frame clock.
+    pub(super) fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
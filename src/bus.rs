@@ -1,106 +1,226 @@
-use crate::cpu::Mem;
-use crate::ppu::PPU;
-const RAM: u16 = 0x0000;
-const RAM_MIRRORS_END: u16 = 0x1FFF;
-const PPU_REGISTERS: u16 = 0x2000;
-const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
-
-pub struct Bus {
-    ram: [u8; 2048],
-    ppu_registers: [u8; 8],
-    apu_io: [u8; 24],
-    cartridge_rom: [u8; 32768],
-    ppu: PPU,
-}
-
-impl Bus {
-    pub fn new() -> Self {
-        Self {
-            ram: [0; 2048],
-            ppu_registers: [0; 8],
-            apu_io: [0; 24],
-            cartridge_rom: [0; 32768],
-            ppu: PPU::new(),
-        }
-    }
-
-    pub fn load_rom(&mut self, rom: &[u8], start_addr: u16) {
-        let start = start_addr as usize;
-        for (i, &byte) in rom.iter().enumerate() {
-            if start_addr >= 0x8000 {
-                let rom_addr = (start + i) - 0x8000;
-                if rom_addr < self.cartridge_rom.len() {
-                    self.cartridge_rom[rom_addr] = byte;
-                }
-            }
-        }
-    }
-
-    pub fn mem_read_u16_zp(&self, pos: u8) -> u16 {
-        let lo = self.mem_read(pos as u16);
-        let hi = self.mem_read(pos.wrapping_add(1) as u16);
-        (hi as u16) << 8 | (lo as u16)
-    }
-
-    pub fn read_register(&self, addr: u16) -> u8 {
-        let register_index = addr & 0x0007;
-        self.ppu.read_register(register_index)
-    }
-}
-
-impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00000111_11111111;
-                self.ram[mirror_down_addr as usize]
-            }
-
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_addr_down = addr & 0x0007;
-                self.ppu.cpu_read(_mirror_addr_down)
-            }
-            0x4000..=0x4017 => self.read_apu_io(addr),
-
-            0x8000..=0xFFFF => {
-                let rom_addr = ((addr - 0x8000) % 0x4000) as usize;
-                self.cartridge_rom[rom_addr]
-            }
-
-            _ => {
-                eprintln!("WARNING: Ignoring mem access at {:#06X}", addr);
-                0
-            }
-        }
-    }
-
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0b00000111_11111111;
-                self.ram[mirror_down_addr as usize] = data;
-            }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0x007;
-                self.ppu.cpu_write(_mirror_down_addr)
-            }
-
-            _ => {
-                eprintln!("WARNING: Ignoring mem write-access at {}", addr);
-            }
-        }
-    }
-
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        let lo = self.mem_read(pos);
-        let hi = self.mem_read(pos.wrapping_add(1));
-        (hi as u16) << 8 | (lo as u16)
-    }
-
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let lo = (data & 0xFF) as u8;
-        let hi = (data >> 8) as u8;
-        self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
-    }
-}
+use crate::apu::APU;
+use crate::cartridge::Mapper;
+use crate::cpu::Mem;
+use crate::cpu::interrupt::IrqSource;
+use crate::joypad::{Joypad, Port};
+use crate::ppu::PPU;
+use crate::region::Region;
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+
+pub struct Bus {
+    ram: [u8; 2048],
+    cartridge: Box<dyn Mapper>,
+    ppu: PPU,
+    apu: APU,
+    joypads: [Joypad; 2],
+    nmi_line: bool,
+    irq_lines: u8,
+    /// Page written to $4014, until the CPU runs the OAM DMA.
+    oam_dma: Option<u8>,
+    region: Region,
+    /// PPU dots owed to the PPU, in units of 1/cycles of a dot, for regions
+    /// that don't run a whole number of dots per CPU cycle.
+    ppu_clock: u32,
+}
+
+impl Bus {
+    pub fn new(cartridge: Box<dyn Mapper>) -> Self {
+        Self {
+            ram: [0; 2048],
+            cartridge,
+            ppu: PPU::new(),
+            apu: APU::new(),
+            joypads: [Joypad::new(), Joypad::new()],
+            nmi_line: false,
+            irq_lines: 0,
+            oam_dma: None,
+            region: Region::default(),
+            ppu_clock: 0,
+        }
+    }
+
+    /// Drives the NMI line on behalf of an external device; the CPU reacts
+    /// to its rising edge.
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.nmi_line = asserted;
+    }
+
+    /// The NMI line, pulled by the PPU at the start of vblank or by an
+    /// external device.
+    pub fn nmi_line(&self) -> bool {
+        self.nmi_line || self.ppu.nmi_line()
+    }
+
+    /// Asserts or releases the IRQ line on behalf of `source`. The CPU sees
+    /// an IRQ for as long as any source holds the line.
+    pub fn set_irq_line(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.irq_lines |= source as u8;
+        } else {
+            self.irq_lines &= !(source as u8);
+        }
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.irq_lines != 0
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    /// Advances the devices on the bus by one CPU cycle.
+    pub fn tick(&mut self) {
+        let (dots, cycles) = self.region.ppu_dots_per_cpu_cycle();
+        self.ppu_clock += dots;
+        while self.ppu_clock >= cycles {
+            self.ppu_clock -= cycles;
+            self.ppu.tick(self.cartridge.as_mut());
+        }
+        self.apu.tick();
+        self.cartridge.tick();
+        let frame_irq = self.apu.frame_irq();
+        self.set_irq_line(IrqSource::FrameCounter, frame_irq);
+        let dmc_irq = self.apu.dmc_irq();
+        self.set_irq_line(IrqSource::Dmc, dmc_irq);
+        let mapper_irq = self.cartridge.irq_pending();
+        self.set_irq_line(IrqSource::Mapper, mapper_irq);
+    }
+
+    /// Takes the page of an OAM DMA requested through $4014.
+    pub(crate) fn take_oam_dma(&mut self) -> Option<u8> {
+        self.oam_dma.take()
+    }
+
+    /// Address of the sample byte the DMC wants fetched by DMA, if any.
+    pub(crate) fn dmc_dma_address(&self) -> Option<u16> {
+        self.apu.dmc_dma_address()
+    }
+
+    pub(crate) fn dmc_dma_fill(&mut self, data: u8) {
+        self.apu.dmc_dma_fill(data);
+    }
+
+    pub fn mem_read_u16_zp(&mut self, pos: u8) -> u16 {
+        let lo = self.mem_read(pos as u16);
+        let hi = self.mem_read(pos.wrapping_add(1) as u16);
+        (hi as u16) << 8 | (lo as u16)
+    }
+
+    /// Reads memory without the side effects of a CPU bus access, for tracing
+    /// and disassembly. I/O registers read as 0.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.ram[(addr & 0b00000111_11111111) as usize],
+            0x4020..=0xFFFF => self.cartridge.cpu_read(addr),
+            _ => 0,
+        }
+    }
+
+    pub fn cartridge(&self) -> &dyn Mapper {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut dyn Mapper {
+        self.cartridge.as_mut()
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    pub fn joypad_mut(&mut self, port: Port) -> &mut Joypad {
+        &mut self.joypads[port as usize]
+    }
+
+    fn read_apu_io(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.apu.read_status(),
+            0x4016 => self.joypads[0].read(),
+            0x4017 => self.joypads[1].read(),
+            _ => 0,
+        }
+    }
+
+    fn write_apu_io(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4016 => {
+                // Both controllers share the strobe line
+                for joypad in self.joypads.iter_mut() {
+                    joypad.write(data);
+                }
+            }
+            0x4014 => self.oam_dma = Some(data),
+            _ => self.apu.cpu_write(addr, data),
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00000111_11111111;
+                self.ram[mirror_down_addr as usize]
+            }
+
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let _mirror_addr_down = addr & 0x0007;
+                self.ppu.cpu_read(_mirror_addr_down, self.cartridge.as_mut())
+            }
+            0x4000..=0x4017 => self.read_apu_io(addr),
+
+            0x4020..=0xFFFF => self.cartridge.cpu_read(addr),
+
+            _ => {
+                eprintln!("WARNING: Ignoring mem access at {:#06X}", addr);
+                0
+            }
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b00000111_11111111;
+                self.ram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let _mirror_down_addr = addr & 0x007;
+                self.ppu.cpu_write(_mirror_down_addr, data, self.cartridge.as_mut())
+            }
+            0x4000..=0x4017 => self.write_apu_io(addr, data),
+            0x4020..=0xFFFF => self.cartridge.cpu_write(addr, data),
+
+            _ => {
+                eprintln!("WARNING: Ignoring mem write-access at {}", addr);
+            }
+        }
+    }
+
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos);
+        let hi = self.mem_read(pos.wrapping_add(1));
+        (hi as u16) << 8 | (lo as u16)
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let lo = (data & 0xFF) as u8;
+        let hi = (data >> 8) as u8;
+        self.mem_write(pos, lo);
+        self.mem_write(pos + 1, hi);
+    }
+}
@@ -0,0 +1,136 @@
+use super::{Mapper, PrgRam, chr_or_ram};
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Boards built from discrete logic: a single latch written anywhere in
+/// $8000-$FFFF selects the PRG and/or CHR banks. Any PRG-RAM the header
+/// declares sits at $6000-$7FFF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Board {
+    /// Mapper 2: 16 KB switchable at $8000, last bank fixed at $C000.
+    UxRom,
+    /// Mapper 3: 8 KB switchable CHR-ROM.
+    CnRom,
+    /// Mapper 7: 32 KB switchable PRG and one-screen mirroring.
+    AxRom,
+    /// Mapper 66: 32 KB PRG in bits 4-5, 8 KB CHR in bits 0-1.
+    GxRom,
+    /// Mapper 11: 32 KB PRG in bits 0-1, 8 KB CHR in bits 4-7.
+    ColorDreams,
+}
+
+impl Board {
+    /// Whether the common revision of the board lets the CPU and the ROM
+    /// drive the data bus at the same time. ANROM, the usual AxROM, does not.
+    pub fn has_bus_conflicts(self) -> bool {
+        self != Board::AxRom
+    }
+}
+
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    latch: u8,
+}
+
+impl Discrete {
+    pub fn new(mut rom: Rom, board: Board, bus_conflicts: bool) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(&mut rom);
+        Self {
+            board,
+            prg_ram: PrgRam::new(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.mirroring,
+            bus_conflicts,
+            latch: 0,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x7FFF;
+        let latch = self.latch as usize;
+        let offset = match self.board {
+            Board::UxRom if addr < PRG_BANK_SIZE => latch * PRG_BANK_SIZE + addr,
+            Board::UxRom => self.prg_rom.len() - PRG_BANK_SIZE + (addr - PRG_BANK_SIZE),
+            Board::CnRom => addr,
+            Board::AxRom => (latch & 0x07) * 2 * PRG_BANK_SIZE + addr,
+            Board::GxRom => ((latch >> 4) & 0x03) * 2 * PRG_BANK_SIZE + addr,
+            Board::ColorDreams => (latch & 0x03) * 2 * PRG_BANK_SIZE + addr,
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = match self.board {
+            Board::CnRom => self.latch as usize,
+            Board::GxRom => (self.latch & 0x03) as usize,
+            Board::ColorDreams => (self.latch >> 4) as usize,
+            Board::UxRom | Board::AxRom => 0,
+        };
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                self.prg_ram.write(addr, data);
+                return;
+            }
+            0x8000..=0xFFFF => {}
+            _ => return,
+        }
+        // The ROM outputs the byte at the written address too, and the
+        // latch sees the AND of the two
+        self.latch = if self.bus_conflicts {
+            data & self.cpu_read(addr)
+        } else {
+            data
+        };
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.board {
+            Board::AxRom if self.latch & 0x10 != 0 => Mirroring::SingleScreenUpper,
+            Board::AxRom => Mirroring::SingleScreenLower,
+            _ => self.mirroring,
+        }
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_mut()
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Mapper, Nametable};
+use crate::rom::Mirroring;
+
+/// Four-screen boards carry 2 KB of VRAM for the nametables at $2800 and
+/// $2C00, next to the console's 2 KB at $2000 and $2400. Wraps the board's
+/// mapper and adds that memory.
+pub struct FourScreen {
+    inner: Box<dyn Mapper>,
+    vram: [u8; 0x800],
+}
+
+impl FourScreen {
+    pub fn new(inner: Box<dyn Mapper>) -> Self {
+        Self {
+            inner,
+            vram: [0; 0x800],
+        }
+    }
+}
+
+impl Mapper for FourScreen {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.inner.cpu_read(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.inner.cpu_write(addr, data)
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.inner.ppu_read(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.inner.ppu_write(addr, data)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FourScreen
+    }
+
+    fn nametable(&self, table: u16) -> Nametable {
+        match table {
+            0 | 1 => Nametable::Ciram(table as u8),
+            _ => Nametable::Cartridge,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16) -> u8 {
+        self.vram[(addr & 0x07FF) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8) {
+        self.vram[(addr & 0x07FF) as usize] = data;
+    }
+
+    fn tick(&mut self) {
+        self.inner.tick()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.inner.irq_pending()
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.inner.battery_ram()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.inner.battery_ram_mut()
+    }
+}
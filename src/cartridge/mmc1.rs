@@ -0,0 +1,161 @@
+use super::{Mapper, PrgRam, chr_or_ram};
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+/// SUROM/SXROM select one of two 256 KB PRG halves with CHR register bit 4.
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// Mapper 1 (SxROM boards). Registers are loaded one bit at a time through a
+/// 5-bit shift register written at $8000-$FFFF.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: PrgRam,
+    shift: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(mut rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(&mut rom);
+        Self {
+            prg_ram: PrgRam::new(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            shift: 0x10,
+            // Power on in PRG mode 3 so the reset vector is in the last bank
+            control: 0x0C,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank0 = value,
+            0xC000..=0xDFFF => self.chr_bank1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let outer = if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            ((self.chr_bank0 as usize >> 4) & 1) * PRG_OUTER_BANK_SIZE
+        } else {
+            0
+        };
+        let last_bank = (self.prg_rom.len().min(PRG_OUTER_BANK_SIZE) / PRG_BANK_SIZE) - 1;
+        let bank = (self.prg_bank & 0x0F) as usize;
+
+        let high = addr >= 0xC000;
+        let bank = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) | high as usize,
+            2 if high => bank,
+            2 => 0,
+            _ if high => last_bank,
+            _ => bank,
+        };
+        let offset = outer + bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        offset % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let high = addr & 0x1000 != 0;
+        let bank = if self.control & 0x10 == 0 {
+            (self.chr_bank0 as usize & !1) | high as usize
+        } else if high {
+            self.chr_bank1 as usize
+        } else {
+            self.chr_bank0 as usize
+        };
+        (bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram.read(addr),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram.write(addr, data);
+            }
+            0x8000..=0xFFFF => {
+                // The serial port ignores a write on the cycle right after
+                // another one, e.g. the second write of an INC/ROR
+                let consecutive = matches!(self.last_write, Some(last) if self.cycle - last <= 1);
+                self.last_write = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+
+                if data & 0x80 != 0 {
+                    self.shift = 0x10;
+                    self.control |= 0x0C;
+                    return;
+                }
+                let full = self.shift & 1 != 0;
+                self.shift = (self.shift >> 1) | ((data & 1) << 4);
+                if full {
+                    self.write_register(addr, self.shift);
+                    self.shift = 0x10;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_mut()
+    }
+}
@@ -0,0 +1,206 @@
+use super::{Mapper, PrgRam, chr_or_ram};
+use crate::rom::{Mirroring, Rom};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+/// A12 must stay low for this many CPU cycles before a rising edge clocks
+/// the IRQ counter, which filters out the toggling within a scanline.
+const A12_LOW_CYCLES: u64 = 3;
+
+/// How the scanline counter treats a reload to 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mmc3Revision {
+    /// MMC3B/MMC3C: an IRQ fires on every clock that leaves the counter at 0,
+    /// so a latch of 0 fires on every scanline.
+    Sharp,
+    /// MMC3A: an IRQ fires only when the counter goes from non-zero to 0 or
+    /// is reloaded through $C001.
+    Nec,
+}
+
+/// Mapper 4 (TxROM boards).
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: PrgRam,
+    four_screen: bool,
+    revision: Mmc3Revision,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    cycle: u64,
+    a12_low_since: Option<u64>,
+}
+
+impl Mmc3 {
+    pub fn new(mut rom: Rom, revision: Mmc3Revision) -> Self {
+        let (chr, chr_is_ram) = chr_or_ram(&mut rom);
+        Self {
+            prg_ram: PrgRam::new(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            four_screen: rom.mirroring == Mirroring::FourScreen,
+            revision,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.mirroring,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq: false,
+            cycle: 0,
+            a12_low_since: Some(0),
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = bank_count.saturating_sub(2);
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        let swap = self.bank_select & 0x40 != 0;
+        let bank = match (slot, swap) {
+            (0, false) | (2, true) => self.banks[6] as usize,
+            (1, _) => self.banks[7] as usize,
+            (0, true) | (2, false) => second_last,
+            _ => bank_count - 1,
+        };
+        (bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))) % self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // With CHR inversion the two 2 KB banks move to $1000
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        } as usize
+            & 0x1FFF;
+        let slot = addr / CHR_BANK_SIZE;
+        let bank = match slot {
+            0..=3 => (self.banks[slot / 2] as usize & !1) | (slot & 1),
+            _ => self.banks[slot - 2] as usize,
+        };
+        (bank * CHR_BANK_SIZE + (addr & (CHR_BANK_SIZE - 1))) % self.chr.len()
+    }
+
+    /// Follows PPU address line A12 and clocks the scanline counter on a
+    /// rising edge that comes after A12 was low long enough.
+    fn watch_a12(&mut self, addr: u16) {
+        if addr & 0x1000 == 0 {
+            if self.a12_low_since.is_none() {
+                self.a12_low_since = Some(self.cycle);
+            }
+            return;
+        }
+        if let Some(since) = self.a12_low_since.take()
+            && self.cycle - since >= A12_LOW_CYCLES
+        {
+            self.clock_irq_counter();
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let previous = self.irq_counter;
+        let reloaded = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+
+        let fire = match self.revision {
+            Mmc3Revision::Sharp => self.irq_counter == 0,
+            Mmc3Revision::Nec => self.irq_counter == 0 && (previous > 0 || reloaded),
+        };
+        if fire && self.irq_enabled {
+            self.irq = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0x80 != 0 => {
+                self.prg_ram.read(addr)
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_protect & 0xC0 == 0x80 => {
+                self.prg_ram.write(addr, data);
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 0x07) as usize] = data,
+            0xA000..=0xBFFF if !even => self.prg_ram_protect = data,
+            // Four-screen boards wire the nametables themselves
+            0xA000..=0xBFFF if !self.four_screen => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.chr[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.watch_a12(addr);
+        if self.chr_is_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.prg_ram.battery()
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        self.prg_ram.battery_mut()
+    }
+}
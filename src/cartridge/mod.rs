@@ -0,0 +1,505 @@
+mod discrete;
+mod four_screen;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod nsf;
+
+use crate::nsf::Nsf;
+use crate::rom::{Mirroring, Rom, RomError};
+use discrete::{Board, Discrete};
+use four_screen::FourScreen;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use nsf::NsfBoard;
+
+pub use mmc3::Mmc3Revision;
+pub use nsf::DRIVER_START as NSF_DRIVER_START;
+
+/// Memory behind one of the four 1 KB nametable slots at $2000-$2FFF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nametable {
+    /// One of the two 1 KB pages of the console's VRAM (CIRAM).
+    Ciram(u8),
+    /// Memory on the cartridge, accessed through `Mapper::nametable_read`
+    /// and `Mapper::nametable_write`.
+    Cartridge,
+}
+
+/// Cartridge hardware as seen from the CPU bus ($4020-$FFFF) and the PPU bus
+/// ($0000-$1FFF pattern tables, and the nametables the cartridge wires up).
+pub trait Mapper {
+    /// Reads a byte from cartridge space. Must not have side effects, so the
+    /// bus can use it for tracing as well.
+    fn cpu_read(&self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    /// Takes `&mut self` because some mappers watch the PPU address lines.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    /// Memory behind nametable slot `table` (0-3, for $2000, $2400, $2800
+    /// and $2C00). Mappers with arbitrary layouts override this.
+    fn nametable(&self, table: u16) -> Nametable {
+        match (self.mirroring(), table) {
+            (Mirroring::Horizontal, _) => Nametable::Ciram((table >> 1) as u8),
+            (Mirroring::Vertical, _) => Nametable::Ciram((table & 1) as u8),
+            (Mirroring::SingleScreenLower, _) => Nametable::Ciram(0),
+            (Mirroring::SingleScreenUpper, _) => Nametable::Ciram(1),
+            (Mirroring::FourScreen, 0 | 1) => Nametable::Ciram(table as u8),
+            (Mirroring::FourScreen, _) => Nametable::Cartridge,
+        }
+    }
+
+    /// Reads a nametable slot mapped to `Nametable::Cartridge`. `addr` is in
+    /// $2000-$2FFF.
+    fn nametable_read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn nametable_write(&mut self, _addr: u16, _data: u8) {}
+
+    /// Called once per CPU cycle, after that cycle's bus access.
+    fn tick(&mut self) {}
+
+    /// Whether the cartridge is holding the IRQ line.
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Battery-backed RAM that should outlive the session, if any.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Builds the mapper for `rom` from its iNES mapper number.
+pub fn from_rom(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    if rom.prg_rom.is_empty() {
+        return Err(RomError::NoPrgRom);
+    }
+
+    let four_screen = rom.mirroring == Mirroring::FourScreen;
+    let mapper: Box<dyn Mapper> = match rom.mapper {
+        0 => Box::new(Nrom::new(rom)),
+        1 => Box::new(Mmc1::new(rom)),
+        2 => discrete(rom, Board::UxRom),
+        3 => discrete(rom, Board::CnRom),
+        4 => {
+            let revision = match rom.submapper {
+                4 => Mmc3Revision::Nec,
+                _ => Mmc3Revision::Sharp,
+            };
+            Box::new(Mmc3::new(rom, revision))
+        }
+        7 => discrete(rom, Board::AxRom),
+        11 => discrete(rom, Board::ColorDreams),
+        66 => discrete(rom, Board::GxRom),
+        mapper => return Err(RomError::UnsupportedMapper(mapper)),
+    };
+
+    if four_screen {
+        Ok(Box::new(FourScreen::new(mapper)))
+    } else {
+        Ok(mapper)
+    }
+}
+
+/// Builds the board an NSF player runs on.
+pub fn from_nsf(nsf: &Nsf) -> Box<dyn Mapper> {
+    Box::new(NsfBoard::new(nsf))
+}
+
+fn discrete(rom: Rom, board: Board) -> Box<dyn Mapper> {
+    // NES 2.0 submappers 1 and 2 of mappers 2, 3 and 7 say whether the
+    // board has bus conflicts
+    let bus_conflicts = match rom.submapper {
+        1 => false,
+        2 => true,
+        _ => board.has_bus_conflicts(),
+    };
+    Box::new(Discrete::new(rom, board, bus_conflicts))
+}
+
+/// Work RAM at $6000-$7FFF, mirrored across the 8 KB window when smaller.
+struct PrgRam {
+    data: Vec<u8>,
+    battery: bool,
+}
+
+impl PrgRam {
+    fn new(rom: &Rom) -> Self {
+        Self {
+            data: vec![0; rom.prg_ram_size + rom.prg_nvram_size],
+            battery: rom.battery || rom.prg_nvram_size > 0,
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+        self.data[(addr - 0x6000) as usize % self.data.len()]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if !self.data.is_empty() {
+            let len = self.data.len();
+            self.data[(addr - 0x6000) as usize % len] = data;
+        }
+    }
+
+    fn battery(&self) -> Option<&[u8]> {
+        (self.battery && !self.data.is_empty()).then_some(self.data.as_slice())
+    }
+
+    fn battery_mut(&mut self) -> Option<&mut [u8]> {
+        (self.battery && !self.data.is_empty()).then_some(self.data.as_mut_slice())
+    }
+}
+
+/// CHR-RAM for boards that ship without CHR-ROM, at least 8 KB.
+fn chr_or_ram(rom: &mut Rom) -> (Vec<u8>, bool) {
+    if rom.chr_rom.is_empty() {
+        let size = (rom.chr_ram_size + rom.chr_nvram_size).max(0x2000);
+        (vec![0; size], true)
+    } else {
+        (std::mem::take(&mut rom.chr_rom), false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(mapper: u16, prg_banks: usize, chr_rom: Vec<u8>) -> Rom {
+        let mut prg_rom = vec![0; prg_banks * 0x4000];
+        for (bank, chunk) in prg_rom.chunks_mut(0x4000).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        Rom {
+            prg_rom,
+            chr_rom,
+            mapper,
+            mirroring: Mirroring::Vertical,
+            prg_ram_size: 0x2000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_nrom_prg_mirroring() {
+        let nrom_128 = from_rom(rom(0, 1, vec![0; 0x2000])).unwrap();
+        assert_eq!(nrom_128.cpu_read(0x8000), 0);
+        assert_eq!(nrom_128.cpu_read(0xC000), 0);
+
+        let nrom_256 = from_rom(rom(0, 2, vec![0; 0x2000])).unwrap();
+        assert_eq!(nrom_256.cpu_read(0x8000), 0);
+        assert_eq!(nrom_256.cpu_read(0xC000), 1);
+        assert_eq!(nrom_256.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_nrom_chr_ram() {
+        let mut chr_rom = from_rom(rom(0, 1, vec![0x11; 0x2000])).unwrap();
+        chr_rom.ppu_write(0x0010, 0x42);
+        assert_eq!(chr_rom.ppu_read(0x0010), 0x11);
+
+        let mut chr_ram = from_rom(rom(0, 1, Vec::new())).unwrap();
+        chr_ram.ppu_write(0x1FFF, 0x42);
+        assert_eq!(chr_ram.ppu_read(0x1FFF), 0x42);
+    }
+
+    /// Loads an MMC1 register through the serial port, one CPU write every
+    /// other cycle.
+    fn mmc1_write(mapper: &mut dyn Mapper, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.cpu_write(addr, (value >> bit) & 1);
+            mapper.tick();
+            mapper.tick();
+        }
+    }
+
+    #[test]
+    fn test_mmc1_prg_banking() {
+        let mut mmc1 = from_rom(rom(1, 8, Vec::new())).unwrap();
+        // Power-on mode 3: switchable $8000, last bank fixed at $C000
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+        mmc1_write(mmc1.as_mut(), 0xE000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), 3);
+        assert_eq!(mmc1.cpu_read(0xC000), 7);
+
+        // Mode 2: first bank fixed at $8000, switchable $C000
+        mmc1_write(mmc1.as_mut(), 0x8000, 0b01000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 3);
+
+        // 32 KB mode ignores the low bank bit
+        mmc1_write(mmc1.as_mut(), 0x8000, 0b00000);
+        mmc1_write(mmc1.as_mut(), 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 4);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+        assert_eq!(mmc1.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_mmc1_chr_banking_and_mirroring() {
+        let chr: Vec<u8> = (0..8).flat_map(|bank| vec![bank; 0x1000]).collect();
+        let mut mmc1 = from_rom(rom(1, 2, chr)).unwrap();
+        // 4 KB CHR mode, horizontal mirroring
+        mmc1_write(mmc1.as_mut(), 0x8000, 0b11111);
+        mmc1_write(mmc1.as_mut(), 0xA000, 5);
+        mmc1_write(mmc1.as_mut(), 0xC000, 2);
+        assert_eq!(mmc1.ppu_read(0x0000), 5);
+        assert_eq!(mmc1.ppu_read(0x1000), 2);
+        assert_eq!(mmc1.mirroring(), Mirroring::Horizontal);
+
+        // 8 KB CHR mode uses CHR bank 0 with the low bit cleared
+        mmc1_write(mmc1.as_mut(), 0x8000, 0b01110);
+        assert_eq!(mmc1.ppu_read(0x0000), 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_mmc1_ignores_consecutive_writes() {
+        let mut mmc1 = from_rom(rom(1, 8, Vec::new())).unwrap();
+        mmc1_write(mmc1.as_mut(), 0xE000, 1);
+        // An RMW instruction writes twice in a row: the reset lands, the
+        // second write does not shift in a bit
+        mmc1.cpu_write(0xE000, 0x80);
+        mmc1.tick();
+        mmc1.cpu_write(0xE000, 0x01);
+        mmc1.tick();
+        mmc1.tick();
+        mmc1_write(mmc1.as_mut(), 0xE000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram_enable() {
+        let mut mmc1 = from_rom(rom(1, 2, Vec::new())).unwrap();
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+        mmc1_write(mmc1.as_mut(), 0xE000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_mmc1_surom_outer_bank() {
+        let mut mmc1 = from_rom(rom(1, 32, Vec::new())).unwrap();
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+        mmc1_write(mmc1.as_mut(), 0xA000, 0x10);
+        assert_eq!(mmc1.cpu_read(0xC000), 31);
+        mmc1_write(mmc1.as_mut(), 0xE000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 18);
+    }
+
+    #[test]
+    fn test_uxrom_banking_with_bus_conflicts() {
+        let mut uxrom = from_rom(rom(2, 8, Vec::new())).unwrap();
+        assert_eq!(uxrom.cpu_read(0xC000), 7);
+        uxrom.cpu_write(0xC000, 3);
+        assert_eq!(uxrom.cpu_read(0x8000), 3);
+        // The ROM drives 0 at $C001, so the write selects bank 0
+        uxrom.cpu_write(0xC001, 3);
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+
+        let mut no_conflicts = Discrete::new(rom(2, 8, Vec::new()), Board::UxRom, false);
+        no_conflicts.cpu_write(0xC001, 3);
+        assert_eq!(no_conflicts.cpu_read(0x8000), 3);
+    }
+
+    #[test]
+    fn test_axrom_one_screen_mirroring() {
+        let mut axrom = from_rom(rom(7, 8, Vec::new())).unwrap();
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+        axrom.cpu_write(0x8000, 0x11);
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(axrom.cpu_read(0x8000), 2);
+        assert_eq!(axrom.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_chr_switching_boards() {
+        let chr: Vec<u8> = (0..4).flat_map(|bank| vec![bank; 0x2000]).collect();
+        for (mapper, value, prg_bank, chr_bank) in
+            [(3, 0x02, 0, 2), (66, 0x12, 1, 2), (11, 0x31, 1, 3)]
+        {
+            let mut rom = rom(mapper, 4, chr.clone());
+            rom.prg_rom.fill(0xFF);
+            rom.prg_rom[0x0000] = 0;
+            rom.prg_rom[0x8000] = 1;
+            let mut board = from_rom(rom).unwrap();
+            board.cpu_write(0x8001, value);
+            assert_eq!(board.cpu_read(0x8000), prg_bank, "mapper {}", mapper);
+            assert_eq!(board.ppu_read(0x0000), chr_bank, "mapper {}", mapper);
+            assert_eq!(board.mirroring(), Mirroring::Vertical);
+        }
+    }
+
+    /// One scanline's worth of pattern fetches: background from $0000 and
+    /// sprites from $1000, with the CPU clock running alongside.
+    fn mmc3_scanline(mapper: &mut dyn Mapper) {
+        for _ in 0..80 {
+            mapper.ppu_read(0x0000);
+            mapper.tick();
+        }
+        for _ in 0..8 {
+            mapper.ppu_read(0x1000);
+            mapper.ppu_read(0x1008);
+        }
+        for _ in 0..5 {
+            mapper.tick();
+        }
+    }
+
+    #[test]
+    fn test_mmc3_banking() {
+        let chr: Vec<u8> = (0..16).flat_map(|bank| vec![bank; 0x400]).collect();
+        let mut prg = rom(4, 4, chr);
+        for (bank, chunk) in prg.prg_rom.chunks_mut(0x2000).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        let mut mmc3 = from_rom(prg).unwrap();
+        for (register, bank) in [(0, 4), (1, 6), (2, 9), (5, 12), (6, 3), (7, 5)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        let prg_banks =
+            |mmc3: &dyn Mapper| [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc3.cpu_read(addr));
+        assert_eq!(prg_banks(mmc3.as_ref()), [3, 5, 6, 7]);
+        assert_eq!(mmc3.ppu_read(0x0400), 5);
+        assert_eq!(mmc3.ppu_read(0x0800), 6);
+        assert_eq!(mmc3.ppu_read(0x1000), 9);
+        assert_eq!(mmc3.ppu_read(0x1C00), 12);
+
+        // PRG mode 1 swaps $8000 and $C000, CHR inversion swaps the halves
+        mmc3.cpu_write(0x8000, 0xC0);
+        assert_eq!(prg_banks(mmc3.as_ref()), [6, 5, 3, 7]);
+        assert_eq!(mmc3.ppu_read(0x0000), 9);
+        assert_eq!(mmc3.ppu_read(0x1400), 5);
+
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut mmc3 = from_rom(rom(4, 4, Vec::new())).unwrap();
+        mmc3.cpu_write(0xC000, 2);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        // Reload to 2, then 1, then 0 raises the IRQ
+        mmc3_scanline(mmc3.as_mut());
+        mmc3_scanline(mmc3.as_mut());
+        assert!(!mmc3.irq_pending());
+        mmc3_scanline(mmc3.as_mut());
+        assert!(mmc3.irq_pending());
+
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn test_mmc3_irq_revisions() {
+        // With a latch of 0, Sharp chips fire on every scanline while NEC
+        // chips only fire right after the $C001 reload
+        for (revision, fires_again) in [(Mmc3Revision::Sharp, true), (Mmc3Revision::Nec, false)] {
+            let mut mmc3 = Mmc3::new(rom(4, 4, Vec::new()), revision);
+            mmc3.cpu_write(0xC000, 0);
+            mmc3.cpu_write(0xC001, 0);
+            mmc3.cpu_write(0xE001, 0);
+            mmc3_scanline(&mut mmc3);
+            assert!(mmc3.irq_pending());
+
+            mmc3.cpu_write(0xE000, 0);
+            mmc3.cpu_write(0xE001, 0);
+            mmc3_scanline(&mut mmc3);
+            assert_eq!(mmc3.irq_pending(), fires_again, "{:?}", revision);
+        }
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut volatile = from_rom(rom(0, 1, Vec::new())).unwrap();
+        volatile.cpu_write(0x6000, 0x42);
+        assert_eq!(volatile.cpu_read(0x6000), 0x42);
+        assert!(volatile.battery_ram().is_none());
+
+        let mut battery = rom(4, 2, Vec::new());
+        battery.battery = true;
+        let mut mmc3 = from_rom(battery).unwrap();
+        mmc3.battery_ram_mut().unwrap()[0x10] = 0x99;
+        mmc3.cpu_write(0x7FFF, 0x42);
+        assert_eq!(mmc3.cpu_read(0x6010), 0x99);
+        assert_eq!(mmc3.battery_ram().unwrap()[0x1FFF], 0x42);
+    }
+
+    #[test]
+    fn test_discrete_prg_ram() {
+        for mapper in [2, 3, 7, 11, 66] {
+            let mut battery = rom(mapper, 2, vec![0; 0x2000]);
+            battery.battery = true;
+            battery.prg_ram_size = 0;
+            battery.prg_nvram_size = 0x2000;
+            let mut board = from_rom(battery).unwrap();
+            board.cpu_write(0x6123, 0x42);
+            assert_eq!(board.cpu_read(0x6123), 0x42, "mapper {}", mapper);
+            assert_eq!(board.battery_ram().unwrap()[0x123], 0x42);
+        }
+
+        let mut no_ram = rom(2, 2, Vec::new());
+        no_ram.prg_ram_size = 0;
+        let mut uxrom = from_rom(no_ram).unwrap();
+        uxrom.cpu_write(0x6000, 0x42);
+        assert_eq!(uxrom.cpu_read(0x6000), 0);
+        assert!(uxrom.battery_ram().is_none());
+    }
+
+    #[test]
+    fn test_unknown_mapper() {
+        let err = from_rom(rom(200, 1, Vec::new())).err().unwrap();
+        assert_eq!(err, RomError::UnsupportedMapper(200));
+    }
+
+    #[test]
+    fn test_nsf_banks() {
+        let mut data = vec![0; 0x2000];
+        data[0] = 0x11;
+        data[0x1000] = 0x22;
+        let nsf = Nsf {
+            load_address: 0x8123,
+            bank_init: [0, 1, 0, 0, 0, 0, 0, 2],
+            data,
+            ..Default::default()
+        };
+        // Bankswitched: the load address only sets the offset into bank 0
+        let mut board = from_nsf(&nsf);
+        assert_eq!(board.cpu_read(0x8123), 0x11);
+        assert_eq!(board.cpu_read(0xA123), 0x11);
+        board.cpu_write(0x5FFA, 1);
+        assert_eq!(board.cpu_read(0xA123), 0x22);
+        // Banks past the end of the program wrap
+        board.cpu_write(0x5FFA, 3);
+        assert_eq!(board.cpu_read(0xA123), 0x11);
+
+        // Flat: loaded in place, bank writes ignored
+        let flat = Nsf {
+            bank_init: [0; 8],
+            ..nsf
+        };
+        let mut board = from_nsf(&flat);
+        board.cpu_write(0x5FF9, 0);
+        assert_eq!(board.cpu_read(0x8123), 0x11);
+        assert_eq!(board.cpu_read(0x9123), 0x22);
+        board.cpu_write(0x6000, 0x42);
+        assert_eq!(board.cpu_read(0x6000), 0x42);
+    }
+}
@@ -0,0 +1,89 @@
+use super::Mapper;
+use crate::nsf::Nsf;
+use crate::rom::Mirroring;
+
+const BANK_SIZE: usize = 0x1000;
+
+/// First address of the player's driver RAM, just below the bank registers.
+pub const DRIVER_START: u16 = 0x5FE0;
+const DRIVER_SIZE: usize = 0x18;
+
+/// Cartridge that plays an NSF: 4 KB program banks at $8000-$FFFF selected
+/// through $5FF8-$5FFF, 8 KB of work RAM and a few bytes of RAM at $5FE0 for
+/// the code that calls INIT and PLAY. There is no CHR.
+pub struct NsfBoard {
+    prg: Vec<u8>,
+    banks: [u8; 8],
+    bankswitched: bool,
+    prg_ram: Vec<u8>,
+    driver: [u8; DRIVER_SIZE],
+}
+
+impl NsfBoard {
+    pub fn new(nsf: &Nsf) -> Self {
+        let bankswitched = nsf.is_bankswitched();
+        // Bankswitched programs are padded so the load address lands at its
+        // offset in the first bank; the others sit in a flat 32 KB image.
+        let padding = if bankswitched {
+            nsf.load_address as usize % BANK_SIZE
+        } else {
+            nsf.load_address as usize - 0x8000
+        };
+        let len = (padding + nsf.data.len())
+            .next_multiple_of(BANK_SIZE)
+            .max(if bankswitched { BANK_SIZE } else { 0x8000 });
+        let mut prg = vec![0; len];
+        let end = (padding + nsf.data.len()).min(len);
+        prg[padding..end].copy_from_slice(&nsf.data[..end - padding]);
+
+        Self {
+            prg,
+            banks: if bankswitched {
+                nsf.bank_init
+            } else {
+                [0, 1, 2, 3, 4, 5, 6, 7]
+            },
+            bankswitched,
+            prg_ram: vec![0; 0x2000],
+            driver: [0; DRIVER_SIZE],
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        self.prg.len() / BANK_SIZE
+    }
+}
+
+impl Mapper for NsfBoard {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x5FE0..=0x5FF7 => self.driver[(addr - DRIVER_START) as usize],
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => {
+                let slot = (addr as usize - 0x8000) / BANK_SIZE;
+                let bank = self.banks[slot] as usize % self.bank_count();
+                self.prg[bank * BANK_SIZE + addr as usize % BANK_SIZE]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5FE0..=0x5FF7 => self.driver[(addr - DRIVER_START) as usize] = data,
+            0x5FF8..=0x5FFF if self.bankswitched => self.banks[(addr - 0x5FF8) as usize] = data,
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+}
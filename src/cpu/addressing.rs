@@ -1,48 +1,65 @@
 use super::types::AddressingMode;
 use super::{CPU, Mem};
 
+fn page_crossed(a: u16, b: u16) -> bool {
+    (a & 0xFF00) != (b & 0xFF00)
+}
+
 impl CPU {
-    pub fn resolve_addr(&mut self, mode: &AddressingMode) -> u16 {
+    /// Resolves the operand address for `mode`, also reporting whether an
+    /// indexed or relative address landed on a different page than its base.
+    pub fn resolve_addr(&mut self, mode: &AddressingMode) -> (u16, bool) {
         match mode {
             AddressingMode::Relative => {
                 let offset = self.fetch_byte() as i8;
-                self.program_counter.wrapping_add(offset as u16)
+                let target = self.program_counter.wrapping_add(offset as u16);
+                (target, page_crossed(self.program_counter, target))
             }
-            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Implied | AddressingMode::Accumulator => (0, false),
             AddressingMode::Immediate => {
                 let addr = self.program_counter;
                 self.program_counter += 1;
-                addr
+                (addr, false)
+            }
+            AddressingMode::ZeroPage => (self.fetch_byte() as u16, false),
+            AddressingMode::ZeroPageX => {
+                (self.fetch_byte().wrapping_add(self.register_x) as u16, false)
+            }
+            AddressingMode::ZeroPageY => {
+                (self.fetch_byte().wrapping_add(self.register_y) as u16, false)
             }
-            AddressingMode::ZeroPage => self.fetch_byte() as u16,
-            AddressingMode::ZeroPageX => self.fetch_byte().wrapping_add(self.register_x) as u16,
-            AddressingMode::ZeroPageY => self.fetch_byte().wrapping_add(self.register_y) as u16,
-            AddressingMode::Absolute => self.fetch_word(),
+            AddressingMode::Absolute => (self.fetch_word(), false),
             AddressingMode::Indirect => {
                 let ptr = self.fetch_word();
-                if ptr & 0x00FF == 0x00FF {
+                let addr = if ptr & 0x00FF == 0x00FF {
                     let lo = self.mem_read(ptr) as u16;
                     let hi = self.mem_read(ptr & 0xFF00) as u16;
                     (hi << 8) | lo
                 } else {
                     self.mem_read_u16(ptr)
-                }
+                };
+                (addr, false)
+            }
+            AddressingMode::AbsoluteX => {
+                let base = self.fetch_word();
+                let addr = base.wrapping_add(self.register_x as u16);
+                (addr, page_crossed(base, addr))
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.fetch_word();
+                let addr = base.wrapping_add(self.register_y as u16);
+                (addr, page_crossed(base, addr))
             }
-            AddressingMode::AbsoluteX => self.fetch_word().wrapping_add(self.register_x as u16),
-            AddressingMode::AbsoluteY => self.fetch_word().wrapping_add(self.register_y as u16),
             AddressingMode::IndirectX => {
                 let base = self.fetch_byte();
                 let ptr = base.wrapping_add(self.register_x);
-                self.bus.mem_read_u16_zp(ptr)
+                (self.bus.mem_read_u16_zp(ptr), false)
             }
             AddressingMode::IndirectY => {
                 let base = self.fetch_byte();
                 let ptr = self.bus.mem_read_u16_zp(base);
-                ptr.wrapping_add(self.register_y as u16)
-            }
-            _ => {
-                eprintln!("WARNING: Addressmode not yet supported");
-                0
+                let addr = ptr.wrapping_add(self.register_y as u16);
+                (addr, page_crossed(ptr, addr))
             }
         }
     }
@@ -0,0 +1,417 @@
+use super::opcodes;
+use super::types::{Access, AddressingMode, Flags, Instruction, Opcode};
+use super::{CPU, Mem};
+
+/// Progress of the cycle-stepped core through the current instruction.
+#[derive(Default)]
+pub struct MicroState {
+    opcode: u8,
+    /// Cycle within the instruction; 0 means the next tick fetches an opcode.
+    step: u8,
+    /// The hardware interrupt sequence runs instead of the next instruction.
+    interrupt: bool,
+    /// Effective address (or branch target) being assembled.
+    addr: u16,
+    /// Zero-page pointer of the indirect addressing modes.
+    pointer: u8,
+    /// Operand latched between the read and write cycles of RMW instructions.
+    data: u8,
+    page_crossed: bool,
+}
+
+/// First cycle of the operand access once the address of `mode` is resolved.
+fn operand_cycle(mode: AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::ZeroPage => 2,
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY | AddressingMode::Absolute => 3,
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => 4,
+        AddressingMode::IndirectX | AddressingMode::IndirectY => 5,
+        _ => 1,
+    }
+}
+
+impl CPU {
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.micro.step == 0
+    }
+
+    pub(super) fn interrupt_sequence_pending(&self) -> bool {
+        self.micro.interrupt
+    }
+
+    /// Runs a single CPU cycle, performing exactly one bus read or write.
+    /// A DMA started during an instruction runs once its last cycle is done.
+    pub fn tick(&mut self) {
+        let done = if self.micro.interrupt {
+            self.tick_interrupt(self.micro.step)
+        } else if self.micro.step == 0 {
+            self.micro.opcode = self.fetch_byte();
+            false
+        } else {
+            let instruction = opcodes::decode(self.micro.opcode);
+            self.tick_instruction(&instruction)
+        };
+        self.end_cycle(self.get_flag(Flags::I));
+
+        if done {
+            // Interrupt sequences and BRK always run the handler's first
+            // instruction before another interrupt can be taken.
+            let interrupt = !self.micro.interrupt
+                && self.micro.opcode != 0x00
+                && self.interrupt_requested();
+            self.micro = MicroState {
+                interrupt,
+                ..MicroState::default()
+            };
+            self.run_pending_dma();
+        } else {
+            self.micro.step += 1;
+        }
+    }
+
+    /// The 7-cycle NMI/IRQ sequence: two discarded reads, three pushes and
+    /// the vector fetch. The vector is chosen when the status is pushed, so
+    /// an NMI arriving before then hijacks an IRQ.
+    fn tick_interrupt(&mut self, step: u8) -> bool {
+        match step {
+            0 | 1 => {
+                self.mem_read(self.program_counter);
+            }
+            2 => self.push((self.program_counter >> 8) as u8),
+            3 => self.push((self.program_counter & 0xFF) as u8),
+            4 => {
+                self.micro.addr = self.interrupt_vector();
+                self.push((self.status & !(Flags::B as u8)) | Flags::U as u8);
+                self.set_flag(Flags::I, true);
+            }
+            5 => self.micro.data = self.mem_read(self.micro.addr),
+            _ => {
+                let high = self.mem_read(self.micro.addr.wrapping_add(1)) as u16;
+                self.program_counter = (high << 8) | self.micro.data as u16;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Performs the current cycle of `instruction`, returning true on its last cycle.
+    fn tick_instruction(&mut self, instruction: &Instruction) -> bool {
+        let opcode = instruction.opcode;
+        let step = self.micro.step;
+        match opcode {
+            Opcode::BCC => self.tick_branch(!self.get_flag(Flags::C), step),
+            Opcode::BCS => self.tick_branch(self.get_flag(Flags::C), step),
+            Opcode::BEQ => self.tick_branch(self.get_flag(Flags::Z), step),
+            Opcode::BMI => self.tick_branch(self.get_flag(Flags::N), step),
+            Opcode::BNE => self.tick_branch(!self.get_flag(Flags::Z), step),
+            Opcode::BPL => self.tick_branch(!self.get_flag(Flags::N), step),
+            Opcode::BVC => self.tick_branch(!self.get_flag(Flags::V), step),
+            Opcode::BVS => self.tick_branch(self.get_flag(Flags::V), step),
+            Opcode::BRK => self.tick_brk(step),
+            Opcode::JMP => self.tick_jmp(instruction.addressing_mode, step),
+            Opcode::JSR => self.tick_jsr(step),
+            Opcode::RTS => self.tick_rts(step),
+            Opcode::RTI => self.tick_rti(step),
+            Opcode::PHA | Opcode::PHP => self.tick_push(opcode, step),
+            Opcode::PLA | Opcode::PLP => self.tick_pull(opcode, step),
+            _ => match instruction.addressing_mode {
+                AddressingMode::Implied | AddressingMode::Accumulator => {
+                    self.mem_read(self.program_counter);
+                    if instruction.addressing_mode == AddressingMode::Accumulator {
+                        self.accumulator = self.rmw_op(opcode, self.accumulator);
+                    } else {
+                        self.implied_op(opcode);
+                    }
+                    true
+                }
+                AddressingMode::Immediate => {
+                    let val = self.fetch_byte();
+                    self.read_op(opcode, val);
+                    true
+                }
+                mode => {
+                    let first = operand_cycle(mode);
+                    if step < first {
+                        self.tick_address(opcode, mode, step)
+                    } else {
+                        self.tick_operand(opcode, step - first)
+                    }
+                }
+            },
+        }
+    }
+
+    /// Address resolution cycles. Returns true only when an indexed read
+    /// needed no page fix-up and so completed the instruction.
+    fn tick_address(&mut self, opcode: Opcode, mode: AddressingMode, step: u8) -> bool {
+        match (mode, step) {
+            (AddressingMode::ZeroPage | AddressingMode::ZeroPageX | AddressingMode::ZeroPageY, 1)
+            | (AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 1) => {
+                self.micro.addr = self.fetch_byte() as u16;
+            }
+            (AddressingMode::ZeroPageX | AddressingMode::ZeroPageY, 2) => {
+                // Dummy read of the unindexed zero-page address
+                self.mem_read(self.micro.addr);
+                let index = if mode == AddressingMode::ZeroPageX {
+                    self.register_x
+                } else {
+                    self.register_y
+                };
+                self.micro.addr = (self.micro.addr as u8).wrapping_add(index) as u16;
+            }
+            (AddressingMode::Absolute, 2) => {
+                self.micro.addr |= (self.fetch_byte() as u16) << 8;
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 2) => {
+                let base = self.micro.addr | (self.fetch_byte() as u16) << 8;
+                let index = if mode == AddressingMode::AbsoluteX {
+                    self.register_x
+                } else {
+                    self.register_y
+                };
+                self.index_address(base, index);
+            }
+            (AddressingMode::IndirectX | AddressingMode::IndirectY, 1) => {
+                self.micro.pointer = self.fetch_byte();
+            }
+            (AddressingMode::IndirectX, 2) => {
+                self.mem_read(self.micro.pointer as u16);
+                self.micro.pointer = self.micro.pointer.wrapping_add(self.register_x);
+            }
+            (AddressingMode::IndirectX, 3) | (AddressingMode::IndirectY, 2) => {
+                self.micro.addr = self.mem_read(self.micro.pointer as u16) as u16;
+            }
+            (AddressingMode::IndirectX, 4) => {
+                let hi = self.mem_read(self.micro.pointer.wrapping_add(1) as u16);
+                self.micro.addr |= (hi as u16) << 8;
+            }
+            (AddressingMode::IndirectY, 3) => {
+                let hi = self.mem_read(self.micro.pointer.wrapping_add(1) as u16);
+                let base = self.micro.addr | (hi as u16) << 8;
+                self.index_address(base, self.register_y);
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 3)
+            | (AddressingMode::IndirectY, 4) => {
+                // The high byte is fixed up one cycle late, so this read hits the
+                // wrong page when indexing carried. Reads that did not carry are done.
+                let uncorrected = if self.micro.page_crossed {
+                    self.micro.addr.wrapping_sub(0x100)
+                } else {
+                    self.micro.addr
+                };
+                let val = self.mem_read(uncorrected);
+                if opcode.is_read() && !self.micro.page_crossed {
+                    self.read_op(opcode, val);
+                    return true;
+                }
+            }
+            _ => eprintln!("WARNING: Addressing cycle {} of {:?} not supported", step, mode),
+        }
+        false
+    }
+
+    fn index_address(&mut self, base: u16, index: u8) {
+        let addr = base.wrapping_add(index as u16);
+        self.micro.page_crossed = (base & 0xFF00) != (addr & 0xFF00);
+        self.micro.addr = addr;
+    }
+
+    /// Operand access cycles once the effective address is known.
+    fn tick_operand(&mut self, opcode: Opcode, step: u8) -> bool {
+        let addr = self.micro.addr;
+        match opcode.access() {
+            Access::Read => {
+                let val = self.mem_read(addr);
+                self.read_op(opcode, val);
+                true
+            }
+            Access::Write => {
+                self.store_op(opcode, addr);
+                true
+            }
+            Access::ReadModifyWrite => match step {
+                0 => {
+                    self.micro.data = self.mem_read(addr);
+                    false
+                }
+                1 => {
+                    // Dummy write of the unmodified value
+                    self.mem_write(addr, self.micro.data);
+                    self.micro.data = self.rmw_op(opcode, self.micro.data);
+                    false
+                }
+                _ => {
+                    self.mem_write(addr, self.micro.data);
+                    true
+                }
+            },
+            Access::None => {
+                self.implied_op(opcode);
+                true
+            }
+        }
+    }
+
+    fn tick_branch(&mut self, condition: bool, step: u8) -> bool {
+        match step {
+            1 => {
+                let offset = self.fetch_byte() as i8;
+                self.micro.addr = self.program_counter.wrapping_add(offset as u16);
+                !condition
+            }
+            2 => {
+                self.delay_new_irq();
+                // Dummy read of the next opcode while PCL is adjusted
+                self.mem_read(self.program_counter);
+                let target = self.micro.addr;
+                self.program_counter = (self.program_counter & 0xFF00) | (target & 0x00FF);
+                if self.program_counter == target {
+                    return true;
+                }
+                false
+            }
+            _ => {
+                // Dummy read from the wrong page before PCH is fixed
+                self.mem_read(self.program_counter);
+                self.program_counter = self.micro.addr;
+                true
+            }
+        }
+    }
+
+    fn tick_brk(&mut self, step: u8) -> bool {
+        match step {
+            1 => {
+                // Padding byte is read and skipped
+                self.fetch_byte();
+            }
+            2 => self.push((self.program_counter >> 8) as u8),
+            3 => self.push((self.program_counter & 0xFF) as u8),
+            4 => {
+                self.micro.addr = self.interrupt_vector();
+                self.push(self.status | 0x30);
+                self.set_flag(Flags::I, true);
+            }
+            5 => self.micro.data = self.mem_read(self.micro.addr),
+            _ => {
+                let high = self.mem_read(self.micro.addr.wrapping_add(1)) as u16;
+                self.program_counter = (high << 8) | self.micro.data as u16;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn tick_jmp(&mut self, mode: AddressingMode, step: u8) -> bool {
+        match step {
+            1 => self.micro.addr = self.fetch_byte() as u16,
+            2 => {
+                let high = self.fetch_byte() as u16;
+                self.micro.addr |= high << 8;
+                if mode == AddressingMode::Absolute {
+                    self.program_counter = self.micro.addr;
+                    return true;
+                }
+            }
+            3 => self.micro.data = self.mem_read(self.micro.addr),
+            _ => {
+                // The pointer's high byte is fetched without carrying into its page
+                let ptr = self.micro.addr;
+                let high = self.mem_read((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF));
+                self.program_counter = (high as u16) << 8 | self.micro.data as u16;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn tick_jsr(&mut self, step: u8) -> bool {
+        match step {
+            1 => self.micro.addr = self.fetch_byte() as u16,
+            2 => {
+                self.mem_read(0x0100 | self.stack_pointer as u16);
+            }
+            3 => self.push((self.program_counter >> 8) as u8),
+            4 => self.push((self.program_counter & 0xFF) as u8),
+            _ => {
+                let high = self.mem_read(self.program_counter) as u16;
+                self.program_counter = (high << 8) | self.micro.addr;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn tick_rts(&mut self, step: u8) -> bool {
+        match step {
+            1 => {
+                self.mem_read(self.program_counter);
+            }
+            2 => {
+                self.mem_read(0x0100 | self.stack_pointer as u16);
+            }
+            3 => self.program_counter = self.pop() as u16,
+            4 => self.program_counter |= (self.pop() as u16) << 8,
+            _ => {
+                self.mem_read(self.program_counter);
+                self.program_counter = self.program_counter.wrapping_add(1);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn tick_rti(&mut self, step: u8) -> bool {
+        match step {
+            1 => {
+                self.mem_read(self.program_counter);
+            }
+            2 => {
+                self.mem_read(0x0100 | self.stack_pointer as u16);
+            }
+            3 => self.status = (self.pop() & 0xEF) | 0x20,
+            4 => self.micro.addr = self.pop() as u16,
+            _ => {
+                let high = self.pop() as u16;
+                self.program_counter = (high << 8) | self.micro.addr;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn tick_push(&mut self, opcode: Opcode, step: u8) -> bool {
+        if step == 1 {
+            self.mem_read(self.program_counter);
+            return false;
+        }
+        if opcode == Opcode::PHA {
+            self.push(self.accumulator);
+        } else {
+            self.push(self.status | 0x30);
+        }
+        true
+    }
+
+    fn tick_pull(&mut self, opcode: Opcode, step: u8) -> bool {
+        match step {
+            1 => {
+                self.mem_read(self.program_counter);
+            }
+            2 => {
+                self.mem_read(0x0100 | self.stack_pointer as u16);
+            }
+            _ => {
+                let val = self.pop();
+                if opcode == Opcode::PLA {
+                    self.accumulator = val;
+                    self.set_zn(self.accumulator);
+                } else {
+                    self.status = (val & 0xEF) | 0x20;
+                }
+                return true;
+            }
+        }
+        false
+    }
+}
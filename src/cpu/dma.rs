@@ -0,0 +1,59 @@
+use super::{CPU, Mem};
+
+impl CPU {
+    /// Runs any DMA requested during the instruction that just finished. The
+    /// DMA unit halts the CPU on its next read, which is the following opcode
+    /// fetch or the first cycle of the interrupt sequence.
+    pub(super) fn run_pending_dma(&mut self) {
+        if let Some(page) = self.bus.take_oam_dma() {
+            self.oam_dma(page);
+        }
+    }
+
+    /// Copies page `page` to OAM through $2004. Takes one halt cycle, one
+    /// more if the DMA would otherwise start reading on a put cycle, and 256
+    /// read/write pairs: 513 or 514 cycles.
+    ///
+    /// A DMC fetch due in the meantime takes over one of the read cycles and
+    /// needs another to realign, adding 2 cycles.
+    fn oam_dma(&mut self, page: u8) {
+        self.stall_cycle();
+        if self.cycles.is_multiple_of(2) {
+            self.stall_cycle();
+        }
+        let base = (page as u16) << 8;
+        for offset in 0..=0xFF {
+            if let Some(addr) = self.bus.dmc_dma_address() {
+                let sample = self.bus.mem_read(addr);
+                self.stall_cycle();
+                self.bus.dmc_dma_fill(sample);
+                self.stall_cycle();
+            }
+            let data = self.bus.mem_read(base | offset);
+            self.stall_cycle();
+            self.bus.mem_write(0x2004, data);
+            self.stall_cycle();
+        }
+    }
+
+    /// Lets a pending DMC sample fetch halt the CPU before it reads `addr`.
+    /// Takes a halt cycle, a dummy cycle, possibly an alignment cycle and
+    /// the fetch itself: 3 or 4 cycles.
+    ///
+    /// The halted CPU keeps driving its read, so `addr` is read once more.
+    /// On the controller ports this shifts out an extra bit.
+    pub(super) fn dmc_dma(&mut self, addr: u16) {
+        let Some(sample_addr) = self.bus.dmc_dma_address() else {
+            return;
+        };
+        self.bus.mem_read(addr);
+        self.stall_cycle();
+        self.stall_cycle();
+        if self.cycles.is_multiple_of(2) {
+            self.stall_cycle();
+        }
+        let sample = self.bus.mem_read(sample_addr);
+        self.stall_cycle();
+        self.bus.dmc_dma_fill(sample);
+    }
+}
@@ -1,6 +1,9 @@
-use super::types::{AddressingMode, Flags, Instruction, Opcode};
+use super::types::{Access, AddressingMode, Flags, Instruction, Opcode};
 use super::{CPU, Mem};
 
+/// Bus-dependent constant ORed into A by the unstable XAA/LXA opcodes.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
 impl CPU {
     pub fn adc(&mut self, val: u8, acc: u8) -> u8 {
         let carry = if self.get_flag(Flags::C) { 1 } else { 0 };
@@ -23,25 +26,243 @@ impl CPU {
         result
     }
 
-    pub fn execute(&mut self, instruction: Instruction) {
-        let addr = self.resolve_addr(&instruction.addressing_mode);
-        let opcode_copy = instruction.opcode;
-        match instruction.opcode {
+    pub fn compare(&mut self, reg: u8, val: u8) {
+        let result = reg.wrapping_sub(val);
+        self.set_flag(Flags::C, reg >= val);
+        self.set_flag(Flags::Z, reg == val);
+        self.set_flag(Flags::N, (result & 0x80) != 0);
+    }
+
+    /// Store used by SHA/SHX/SHY/TAS: the value is ANDed with the high byte of
+    /// the un-indexed base address plus one, and when indexing crosses a page
+    /// that same value replaces the high byte of the target address.
+    fn store_high_and(&mut self, addr: u16, index: u8, value: u8) {
+        let base = addr.wrapping_sub(index as u16);
+        let high = ((base >> 8) as u8).wrapping_add(1);
+        let result = value & high;
+        let target = if (base & 0xFF00) != (addr & 0xFF00) {
+            ((result as u16) << 8) | (addr & 0x00FF)
+        } else {
+            addr
+        };
+        self.mem_write(target, result);
+    }
+
+    /// Takes a branch when `condition` holds, returning the extra cycles spent:
+    /// one for a taken branch and another if the target is on a different page.
+    fn branch(&mut self, condition: bool, target: u16, page_crossed: bool) -> u8 {
+        if !condition {
+            return 0;
+        }
+        self.program_counter = target;
+        if page_crossed { 2 } else { 1 }
+    }
+
+    /// Applies a read instruction to the operand value fetched from memory.
+    pub(super) fn read_op(&mut self, opcode: Opcode, val: u8) {
+        match opcode {
             Opcode::LDA => {
-                self.accumulator = self.mem_read(addr);
+                self.accumulator = val;
                 self.set_zn(self.accumulator);
             }
             Opcode::LDX => {
-                self.register_x = self.mem_read(addr);
+                self.register_x = val;
                 self.set_zn(self.register_x);
             }
             Opcode::LDY => {
-                self.register_y = self.mem_read(addr);
+                self.register_y = val;
                 self.set_zn(self.register_y);
             }
+            Opcode::ADC => self.accumulator = self.adc(val, self.accumulator),
+            Opcode::SBC => self.accumulator = self.sbc(self.accumulator, val),
+            Opcode::AND => {
+                self.accumulator &= val;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::ORA => {
+                self.accumulator |= val;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::EOR => {
+                self.accumulator ^= val;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::BIT => {
+                self.set_flag(Flags::N, (val & 0x80) != 0);
+                self.set_flag(Flags::V, (val & 0x40) != 0);
+                self.set_flag(Flags::Z, (val & self.accumulator) == 0);
+            }
+            Opcode::CMP => self.compare(self.accumulator, val),
+            Opcode::CPX => self.compare(self.register_x, val),
+            Opcode::CPY => self.compare(self.register_y, val),
+            // Unofficial NOPs with an operand still perform the read.
+            Opcode::NOP => {}
+            Opcode::LAX => {
+                self.accumulator = val;
+                self.register_x = self.accumulator;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::LXA => {
+                self.accumulator = (self.accumulator | UNSTABLE_MAGIC) & val;
+                self.register_x = self.accumulator;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::ANC => {
+                self.accumulator &= val;
+                self.set_zn(self.accumulator);
+                self.set_flag(Flags::C, (self.accumulator & 0x80) != 0);
+            }
+            Opcode::ALR => {
+                let val = self.accumulator & val;
+                self.set_flag(Flags::C, (val & 0x01) != 0);
+                self.accumulator = val >> 1;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::ARR => {
+                let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
+                let val = self.accumulator & val;
+                self.accumulator = val >> 1 | (carry_flag << 7);
+                self.set_zn(self.accumulator);
+                let bit6 = (self.accumulator >> 6) & 1;
+                let bit5 = (self.accumulator >> 5) & 1;
+                self.set_flag(Flags::C, bit6 != 0);
+                self.set_flag(Flags::V, (bit6 ^ bit5) != 0);
+            }
+            Opcode::AXS => {
+                let and = self.accumulator & self.register_x;
+                self.set_flag(Flags::C, and >= val);
+                self.register_x = and.wrapping_sub(val);
+                self.set_zn(self.register_x);
+            }
+            Opcode::XAA => {
+                self.accumulator = (self.accumulator | UNSTABLE_MAGIC) & self.register_x & val;
+                self.set_zn(self.accumulator);
+            }
+            Opcode::LAS => {
+                let val = val & self.stack_pointer;
+                self.accumulator = val;
+                self.register_x = val;
+                self.stack_pointer = val;
+                self.set_zn(val);
+            }
+            _ => eprintln!("WARNING: Opcode {:#?} is not a read instruction", opcode),
+        }
+    }
+
+    /// Applies a read-modify-write instruction to `val`, returning the value
+    /// written back (to memory, or to A for the accumulator forms).
+    pub(super) fn rmw_op(&mut self, opcode: Opcode, val: u8) -> u8 {
+        let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
+        match opcode {
+            Opcode::INC => {
+                let res = val.wrapping_add(1);
+                self.set_zn(res);
+                res
+            }
+            Opcode::DEC => {
+                let res = val.wrapping_sub(1);
+                self.set_zn(res);
+                res
+            }
+            Opcode::ASL => {
+                self.set_flag(Flags::C, (val & 0x80) != 0);
+                let result = val << 1;
+                self.set_zn(result);
+                result
+            }
+            Opcode::LSR => {
+                self.set_flag(Flags::C, (val & 0x01) != 0);
+                let result = val >> 1;
+                self.set_zn(result);
+                result
+            }
+            Opcode::ROL => {
+                self.set_flag(Flags::C, (val & 0x80) != 0);
+                let result = val << 1 | carry_flag;
+                self.set_zn(result);
+                result
+            }
+            Opcode::ROR => {
+                self.set_flag(Flags::C, (val & 0x01) != 0);
+                let result = val >> 1 | (carry_flag << 7);
+                self.set_zn(result);
+                result
+            }
+            Opcode::DCP => {
+                let res = val.wrapping_sub(1);
+                self.compare(self.accumulator, res);
+                res
+            }
+            Opcode::ISB => {
+                let res = val.wrapping_add(1);
+                self.accumulator = self.sbc(self.accumulator, res);
+                res
+            }
+            Opcode::SLO => {
+                self.set_flag(Flags::C, (val & 0x80) != 0);
+                let result = val << 1;
+                self.accumulator |= result;
+                self.set_zn(self.accumulator);
+                result
+            }
+            Opcode::RLA => {
+                self.set_flag(Flags::C, (val & 0x80) != 0);
+                let result = val << 1 | carry_flag;
+                self.accumulator &= result;
+                self.set_zn(self.accumulator);
+                result
+            }
+            Opcode::SRE => {
+                self.set_flag(Flags::C, (val & 0x01) != 0);
+                let result = val >> 1;
+                self.accumulator ^= result;
+                self.set_zn(self.accumulator);
+                result
+            }
+            Opcode::RRA => {
+                self.set_flag(Flags::C, (val & 0x01) != 0);
+                let result = val >> 1 | (carry_flag << 7);
+                self.accumulator = self.adc(result, self.accumulator);
+                result
+            }
+            _ => {
+                eprintln!("WARNING: Opcode {:#?} is not a read-modify-write instruction", opcode);
+                val
+            }
+        }
+    }
+
+    /// Performs the single bus write of a store instruction.
+    pub(super) fn store_op(&mut self, opcode: Opcode, addr: u16) {
+        match opcode {
             Opcode::STA => self.mem_write(addr, self.accumulator),
             Opcode::STX => self.mem_write(addr, self.register_x),
             Opcode::STY => self.mem_write(addr, self.register_y),
+            Opcode::SAX => self.mem_write(addr, self.accumulator & self.register_x),
+            Opcode::SHA => {
+                let index = self.register_y;
+                self.store_high_and(addr, index, self.accumulator & self.register_x);
+            }
+            Opcode::SHX => {
+                let index = self.register_y;
+                self.store_high_and(addr, index, self.register_x);
+            }
+            Opcode::SHY => {
+                let index = self.register_x;
+                self.store_high_and(addr, index, self.register_y);
+            }
+            Opcode::TAS => {
+                self.stack_pointer = self.accumulator & self.register_x;
+                let index = self.register_y;
+                self.store_high_and(addr, index, self.stack_pointer);
+            }
+            _ => eprintln!("WARNING: Opcode {:#?} is not a store instruction", opcode),
+        }
+    }
+
+    /// Executes an instruction that touches neither memory nor the stack.
+    pub(super) fn implied_op(&mut self, opcode: Opcode) {
+        match opcode {
             Opcode::TAX => {
                 self.register_x = self.accumulator;
                 self.set_zn(self.register_x);
@@ -63,14 +284,6 @@ impl CPU {
                 self.accumulator = self.register_y;
                 self.set_zn(self.accumulator);
             }
-            Opcode::ADC => self.accumulator = self.adc(self.mem_read(addr), self.accumulator),
-            Opcode::SBC => self.accumulator = self.sbc(self.accumulator, self.mem_read(addr)),
-            Opcode::INC => {
-                let value = self.mem_read(addr);
-                let res = value.wrapping_add(1);
-                self.mem_write(addr, res);
-                self.set_zn(res);
-            }
             Opcode::INX => {
                 let res = self.register_x.wrapping_add(1);
                 self.register_x = res;
@@ -81,12 +294,6 @@ impl CPU {
                 self.register_y = res;
                 self.set_zn(res);
             }
-            Opcode::DEC => {
-                let value = self.mem_read(addr);
-                let res = value.wrapping_sub(1);
-                self.mem_write(addr, res);
-                self.set_zn(res);
-            }
             Opcode::DEX => {
                 let res = self.register_x.wrapping_sub(1);
                 self.register_x = res;
@@ -97,133 +304,65 @@ impl CPU {
                 self.register_y = res;
                 self.set_zn(res);
             }
-            Opcode::ASL => {
-                if instruction.addressing_mode == AddressingMode::Accumulator {
-                    self.set_flag(Flags::C, (self.accumulator & 0x80) != 0);
-                    self.accumulator = self.accumulator << 1;
-                    self.set_zn(self.accumulator);
-                } else {
-                    let val = self.mem_read(addr);
-                    self.set_flag(Flags::C, (val & 0x80) != 0);
-                    let result = val << 1;
-                    self.mem_write(addr, result);
-                    self.set_zn(result);
-                }
-            }
-            Opcode::LSR => {
-                if instruction.addressing_mode == AddressingMode::Accumulator {
-                    self.set_flag(Flags::C, (self.accumulator & 0x01) != 0);
-                    self.accumulator = self.accumulator >> 1;
-                    self.set_zn(self.accumulator);
-                } else {
-                    let val = self.mem_read(addr);
-                    self.set_flag(Flags::C, (val & 0x01) != 0);
-                    let result = val >> 1;
-                    self.mem_write(addr, result);
-                    self.set_zn(result);
-                }
-            }
-            Opcode::ROL => {
-                if instruction.addressing_mode == AddressingMode::Accumulator {
-                    let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
-                    self.set_flag(Flags::C, (self.accumulator & 0x80) != 0);
-                    self.accumulator = self.accumulator << 1 | carry_flag;
-                    self.set_zn(self.accumulator);
-                } else {
-                    let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
-                    let value = self.mem_read(addr);
-                    self.set_flag(Flags::C, (value & 0x80) != 0);
-                    let result = value << 1 | carry_flag;
-                    self.mem_write(addr, result);
-                    self.set_zn(result);
-                }
+            Opcode::CLC => {
+                self.set_flag(Flags::C, false);
             }
-            Opcode::ROR => {
-                if instruction.addressing_mode == AddressingMode::Accumulator {
-                    let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
-                    self.set_flag(Flags::C, (self.accumulator & 0x01) != 0);
-                    self.accumulator = self.accumulator >> 1 | (carry_flag << 7);
-                    self.set_zn(self.accumulator);
-                } else {
-                    let carry_flag = if self.get_flag(Flags::C) { 1 } else { 0 };
-                    let value = self.mem_read(addr);
-                    self.set_flag(Flags::C, (value & 0x01) != 0);
-                    let result = value >> 1 | (carry_flag << 7);
-                    self.mem_write(addr, result);
-                    self.set_zn(result);
-                }
+            Opcode::CLD => {
+                self.set_flag(Flags::D, false);
             }
-            Opcode::AND => {
-                let val = self.mem_read(addr);
-                self.accumulator = val & self.accumulator;
-                self.set_zn(self.accumulator);
+            Opcode::CLI => {
+                self.set_flag(Flags::I, false);
             }
-            Opcode::ORA => {
-                let val = self.mem_read(addr);
-                self.accumulator = val | self.accumulator;
-                self.set_zn(self.accumulator);
+            Opcode::CLV => {
+                self.set_flag(Flags::V, false);
             }
-            Opcode::EOR => {
-                let val = self.mem_read(addr);
-                self.accumulator = self.accumulator ^ val;
-                self.set_zn(self.accumulator);
+            Opcode::SEC => {
+                self.set_flag(Flags::C, true);
             }
-            Opcode::BIT => {
-                let val = self.mem_read(addr);
-                self.set_flag(Flags::N, (val & 0x80) != 0);
-                self.set_flag(Flags::V, (val & 0x40) != 0);
-                self.set_flag(Flags::Z, (val & self.accumulator) == 0);
+            Opcode::SED => {
+                self.set_flag(Flags::D, true);
             }
-            Opcode::CMP => {
-                let val = self.mem_read(addr);
-                let result = self.accumulator.wrapping_sub(val);
-                self.set_flag(Flags::C, self.accumulator >= val);
-                self.set_flag(Flags::Z, self.accumulator == val);
-                self.set_flag(Flags::N, (result & 0x80) != 0);
-            }
-            Opcode::CPX => {
-                let val = self.mem_read(addr);
-                let result = self.register_x.wrapping_sub(val);
-                self.set_flag(Flags::C, self.register_x >= val);
-                self.set_flag(Flags::Z, self.register_x == val);
-                self.set_flag(Flags::N, (result & 0x80) != 0);
-            }
-            Opcode::CPY => {
-                let val = self.mem_read(addr);
-                let result = self.register_y.wrapping_sub(val);
-                self.set_flag(Flags::C, self.register_y >= val);
-                self.set_flag(Flags::Z, self.register_y == val);
-                self.set_flag(Flags::N, (result & 0x80) != 0);
+            Opcode::SEI => {
+                self.set_flag(Flags::I, true);
             }
+            Opcode::NOP => {}
+            _ => eprintln!("WARNING: Opcode {:#?} not yet supported", opcode),
+        }
+    }
+
+    /// Executes `instruction` and returns the cycles it took beyond the base
+    /// count in the decode table.
+    pub fn execute(&mut self, instruction: Instruction) -> u8 {
+        let (addr, page_crossed) = self.resolve_addr(&instruction.addressing_mode);
+        let opcode = instruction.opcode;
+        let mut extra_cycles = 0;
+        if page_crossed && opcode.is_read() {
+            extra_cycles += 1;
+        }
+        match opcode {
             Opcode::BCC => {
-                if !(self.get_flag(Flags::C)) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(!self.get_flag(Flags::C), addr, page_crossed);
             }
             Opcode::BCS => {
-                if self.get_flag(Flags::C) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(self.get_flag(Flags::C), addr, page_crossed);
             }
             Opcode::BEQ => {
-                if self.get_flag(Flags::Z) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(self.get_flag(Flags::Z), addr, page_crossed);
             }
             Opcode::BMI => {
-                if self.get_flag(Flags::N) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(self.get_flag(Flags::N), addr, page_crossed);
             }
             Opcode::BNE => {
-                if !self.get_flag(Flags::Z) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(!self.get_flag(Flags::Z), addr, page_crossed);
             }
             Opcode::BPL => {
-                if !self.get_flag(Flags::N) {
-                    self.program_counter = addr;
-                }
+                extra_cycles += self.branch(!self.get_flag(Flags::N), addr, page_crossed);
+            }
+            Opcode::BVC => {
+                extra_cycles += self.branch(!self.get_flag(Flags::V), addr, page_crossed);
+            }
+            Opcode::BVS => {
+                extra_cycles += self.branch(self.get_flag(Flags::V), addr, page_crossed);
             }
             Opcode::BRK => {
                 self.program_counter += 1; // Skip padding byte
@@ -231,31 +370,10 @@ impl CPU {
                 let low = (self.program_counter & 0xFF) as u8;
                 self.push(high);
                 self.push(low);
+                let vector = self.interrupt_vector();
                 self.push(self.status | 0x30); // Push status with B and U flags set
                 self.set_flag(Flags::I, true);
-                self.load_irq_pc();
-            }
-            Opcode::BVC => {
-                if !self.get_flag(Flags::V) {
-                    self.program_counter = addr;
-                }
-            }
-            Opcode::BVS => {
-                if self.get_flag(Flags::V) {
-                    self.program_counter = addr;
-                }
-            }
-            Opcode::CLC => {
-                self.set_flag(Flags::C, false);
-            }
-            Opcode::CLD => {
-                self.set_flag(Flags::D, false);
-            }
-            Opcode::CLI => {
-                self.set_flag(Flags::I, false);
-            }
-            Opcode::CLV => {
-                self.set_flag(Flags::V, false);
+                self.program_counter = self.mem_read_u16(vector);
             }
             Opcode::JMP => {
                 self.program_counter = addr;
@@ -268,15 +386,6 @@ impl CPU {
                 self.push(low);
                 self.program_counter = addr;
             }
-            Opcode::SEC => {
-                self.set_flag(Flags::C, true);
-            }
-            Opcode::SED => {
-                self.set_flag(Flags::D, true);
-            }
-            Opcode::SEI => {
-                self.set_flag(Flags::I, true);
-            }
             Opcode::PHA => {
                 self.push(self.accumulator);
             }
@@ -302,8 +411,30 @@ impl CPU {
                 let high = self.pop();
                 self.program_counter = ((high as u16) << 8) | (low as u16);
             }
-            Opcode::NOP => {}
-            _ => eprintln!("WARNING: Opcode {:#?} not yet supported", opcode_copy),
+            _ if instruction.addressing_mode == AddressingMode::Implied => {
+                self.implied_op(opcode)
+            }
+            _ => match opcode.access() {
+                Access::Read => {
+                    let val = self.mem_read(addr);
+                    self.read_op(opcode, val);
+                }
+                Access::Write => self.store_op(opcode, addr),
+                Access::ReadModifyWrite => {
+                    if instruction.addressing_mode == AddressingMode::Accumulator {
+                        self.accumulator = self.rmw_op(opcode, self.accumulator);
+                    } else {
+                        let val = self.mem_read(addr);
+                        // Dummy write of the unmodified value, which mappers
+                        // with write-sensitive registers can observe
+                        self.mem_write(addr, val);
+                        let result = self.rmw_op(opcode, val);
+                        self.mem_write(addr, result);
+                    }
+                }
+                Access::None => self.implied_op(opcode),
+            },
         }
+        extra_cycles
     }
 }
@@ -0,0 +1,128 @@
+use super::types::Flags;
+use super::{CPU, Mem};
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// Devices that can pull the shared, level-triggered IRQ line low.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqSource {
+    External = (1 << 0),
+    FrameCounter = (1 << 1),
+    Dmc = (1 << 2),
+    Mapper = (1 << 3),
+}
+
+/// Interrupt line sampling, updated at the end of every CPU cycle.
+///
+/// The 6502 decides whether to take an interrupt from the line state at the
+/// end of an instruction's second-to-last cycle, so each value is kept for
+/// two cycles: the `*_poll` fields are what the instruction boundary sees.
+#[derive(Default)]
+pub struct InterruptState {
+    /// NMI line level seen last cycle, for edge detection.
+    nmi_line: bool,
+    /// Set by a falling NMI edge until the NMI sequence starts.
+    nmi_pending: bool,
+    nmi_poll: bool,
+    irq_active: bool,
+    irq_poll: bool,
+}
+
+impl CPU {
+    /// Power-on/reset sequence: loads PC from the RESET vector and accounts
+    /// for the 7 cycles the CPU spends before its first fetch.
+    pub fn reset(&mut self) {
+        self.accumulator = 0;
+        self.register_x = 0;
+        self.register_y = 0;
+        self.stack_pointer = 0xFD;
+        self.status = 0x24;
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+        self.cycles = 7;
+        self.micro = Default::default();
+        self.interrupt = InterruptState::default();
+    }
+
+    /// Samples the NMI and IRQ lines at the end of a cycle. `irq_masked` is
+    /// the I flag as it stood during that cycle.
+    pub(super) fn poll_interrupts(&mut self, irq_masked: bool) {
+        let state = &mut self.interrupt;
+        state.nmi_poll = state.nmi_pending;
+        let nmi_line = self.bus.nmi_line();
+        if nmi_line && !state.nmi_line {
+            state.nmi_pending = true;
+        }
+        state.nmi_line = nmi_line;
+
+        state.irq_poll = state.irq_active;
+        state.irq_active = self.bus.irq_line() && !irq_masked;
+    }
+
+    /// A taken branch ignores an IRQ that first shows up during its operand
+    /// fetch, so one more instruction runs before the IRQ is taken.
+    pub(super) fn delay_new_irq(&mut self) {
+        if self.interrupt.irq_active && !self.interrupt.irq_poll {
+            self.interrupt.irq_active = false;
+        }
+    }
+
+    /// Whether the instruction that just finished is followed by an interrupt.
+    pub(super) fn interrupt_requested(&self) -> bool {
+        self.interrupt.nmi_poll || self.interrupt.irq_poll
+    }
+
+    /// Picks the vector once the status byte is pushed. A pending NMI takes
+    /// over IRQ and BRK sequences that are already under way.
+    pub(super) fn interrupt_vector(&mut self) -> u16 {
+        if self.interrupt.nmi_pending {
+            self.interrupt.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            IRQ_VECTOR
+        }
+    }
+
+    /// Runs the 7-cycle hardware interrupt sequence for the instruction core.
+    pub(super) fn service_interrupt(&mut self) {
+        let masked = self.get_flag(Flags::I);
+        // Opcode fetch and operand read are performed but discarded
+        self.mem_read(self.program_counter);
+        self.mem_read(self.program_counter);
+        self.push((self.program_counter >> 8) as u8);
+        self.push((self.program_counter & 0xFF) as u8);
+        for _ in 0..4 {
+            self.end_cycle(masked);
+        }
+
+        let vector = self.interrupt_vector();
+        self.push((self.status & !(Flags::B as u8)) | Flags::U as u8);
+        self.set_flag(Flags::I, true);
+        self.program_counter = self.mem_read_u16(vector);
+        for _ in 0..3 {
+            self.end_cycle(true);
+        }
+    }
+
+    /// Completes one CPU cycle of time after its bus access.
+    pub(super) fn end_cycle(&mut self, irq_masked: bool) {
+        self.cycles += 1;
+        self.bus.tick();
+        self.poll_interrupts(irq_masked);
+    }
+
+    /// Completes a cycle in which DMA holds the CPU halted. NMI edges are
+    /// still latched, but the interrupt polled by the last instruction
+    /// stands until the next one polls again.
+    pub(super) fn stall_cycle(&mut self) {
+        self.cycles += 1;
+        self.bus.tick();
+        let nmi_line = self.bus.nmi_line();
+        if nmi_line && !self.interrupt.nmi_line {
+            self.interrupt.nmi_pending = true;
+        }
+        self.interrupt.nmi_line = nmi_line;
+    }
+}
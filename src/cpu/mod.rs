@@ -1,10 +1,15 @@
 mod addressing;
+mod cycle;
+mod dma;
 mod execute;
+pub mod interrupt;
 mod opcodes;
 pub mod types;
 
 use crate::bus::Bus;
-use types::{AddressingMode, Flags, Instruction, Opcode};
+use cycle::MicroState;
+use interrupt::InterruptState;
+use types::{AddressingMode, ExecutionMode, Flags, Instruction, Opcode};
 
 pub struct CPU {
     accumulator: u8,
@@ -15,17 +20,21 @@ pub struct CPU {
     status: u8,
     bus: Bus,
     cycles: u64,
+    mode: ExecutionMode,
+    micro: MicroState,
+    interrupt: InterruptState,
 }
 
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    fn mem_read(&mut self, addr: u16) -> u8;
     fn mem_write(&mut self, addr: u16, data: u8);
-    fn mem_read_u16(&self, pos: u16) -> u16;
+    fn mem_read_u16(&mut self, pos: u16) -> u16;
     fn mem_write_u16(&mut self, pos: u16, data: u16);
 }
 
 impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.dmc_dma(addr);
         self.bus.mem_read(addr)
     }
 
@@ -33,8 +42,10 @@ impl Mem for CPU {
         self.bus.mem_write(addr, data)
     }
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        self.bus.mem_read_u16(pos)
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos);
+        let hi = self.mem_read(pos.wrapping_add(1));
+        (hi as u16) << 8 | (lo as u16)
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
@@ -43,7 +54,7 @@ impl Mem for CPU {
 }
 
 impl CPU {
-    pub fn new() -> Self {
+    pub fn new(bus: Bus) -> Self {
         Self {
             accumulator: 0,
             program_counter: 0x8000,
@@ -51,29 +62,107 @@ impl CPU {
             register_y: 0,
             stack_pointer: 0xFD,
             status: 0x24,
-            bus: Bus::new(),
+            bus,
             cycles: 0,
+            mode: ExecutionMode::default(),
+            micro: MicroState::default(),
+            interrupt: InterruptState::default(),
         }
     }
 
+    /// Switches between the instruction-stepped and cycle-stepped cores.
+    /// Takes effect at the next instruction boundary.
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+        while !self.at_instruction_boundary() {
+            self.tick();
+        }
+        self.mode = mode;
+    }
+
     pub fn set_pc(&mut self, pc: u16) {
         self.program_counter = pc;
     }
 
-    pub fn load(&mut self, rom: &[u8]) {
-        self.bus.load_rom(rom, 0x8000);
+    pub fn accumulator(&self) -> u8 {
+        self.accumulator
+    }
+
+    pub fn register_x(&self) -> u8 {
+        self.register_x
+    }
+
+    pub fn register_y(&self) -> u8 {
+        self.register_y
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    pub fn status(&self) -> u8 {
+        self.status
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
+    pub(crate) fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub(crate) fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    /// Runs one full instruction with the selected execution mode, followed
+    /// by the interrupt sequence if an interrupt was polled during it.
     pub fn step(&mut self) {
-        let opcode = self.fetch_byte();
-        let instruction = self.decode(opcode);
-        let cycles_used = instruction.cycles as u64;
-        self.execute(instruction);
-        self.cycles += cycles_used;
+        match self.mode {
+            ExecutionMode::Instruction => {
+                let opcode = self.fetch_byte();
+                let instruction = self.decode(opcode);
+                let base_cycles = instruction.cycles as u64;
+                let is_branch = instruction.addressing_mode == AddressingMode::Relative;
+                let is_brk = instruction.opcode == Opcode::BRK;
+                // CLI, SEI and PLP change I after the interrupt poll has happened
+                let delays_i = matches!(instruction.opcode, Opcode::CLI | Opcode::SEI | Opcode::PLP);
+                let masked_before = self.get_flag(Flags::I);
+
+                let extra_cycles = self.execute(instruction);
+                let cycles = base_cycles + extra_cycles as u64;
+                for cycle in 1..=cycles {
+                    let masked = if delays_i && cycle < cycles {
+                        masked_before
+                    } else {
+                        self.get_flag(Flags::I)
+                    };
+                    self.end_cycle(masked);
+                    if is_branch && extra_cycles > 0 && cycle == 2 {
+                        self.delay_new_irq();
+                    }
+                }
+                self.run_pending_dma();
+
+                if !is_brk && self.interrupt_requested() {
+                    self.service_interrupt();
+                }
+            }
+            ExecutionMode::Cycle => {
+                self.tick();
+                while !self.at_instruction_boundary() || self.interrupt_sequence_pending() {
+                    self.tick();
+                }
+            }
+        }
     }
 
     pub fn fetch_byte(&mut self) -> u8 {
-        let opcode = self.mem_read(self.program_counter) as u8;
+        let opcode = self.mem_read(self.program_counter);
         self.program_counter += 1;
         opcode
     }
@@ -86,7 +175,7 @@ impl CPU {
 
     pub fn trace(&self) -> String {
         let pc = self.program_counter;
-        let opcode = self.mem_read(pc);
+        let opcode = self.bus.peek(pc);
         let instruction = self.decode(opcode);
 
         // Read instruction bytes (1-3 bytes)
@@ -101,12 +190,12 @@ impl CPU {
             | AddressingMode::IndirectX
             | AddressingMode::IndirectY
             | AddressingMode::Relative => {
-                let byte1 = self.mem_read(pc + 1);
+                let byte1 = self.bus.peek(pc + 1);
                 format!("{:02X} {:02X}   ", opcode, byte1)
             }
             _ => {
-                let byte1 = self.mem_read(pc + 1);
-                let byte2 = self.mem_read(pc + 2);
+                let byte1 = self.bus.peek(pc + 1);
+                let byte2 = self.bus.peek(pc + 2);
                 format!("{:02X} {:02X} {:02X}", opcode, byte1, byte2)
             }
         };
@@ -114,11 +203,15 @@ impl CPU {
         // Disassemble instruction
         let disasm = self.disassemble(pc, &instruction);
 
+        // nestest.log marks unofficial opcodes with a `*` in the column before the mnemonic
+        let marker = if opcodes::is_unofficial(opcode) { '*' } else { ' ' };
+
         // Format: PC  BYTES  INSTRUCTION                      A:XX X:XX Y:XX P:XX SP:XX PPU:XXX,XXX CYC:XXX
         format!(
-            "{:04X}  {}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            "{:04X}  {} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
             pc,
             bytes,
+            marker,
             disasm,
             self.accumulator,
             self.register_x,
@@ -136,99 +229,105 @@ impl CPU {
             AddressingMode::Implied => mnemonic,
             AddressingMode::Accumulator => format!("{:?} {}", instruction.opcode, "A"),
             AddressingMode::Immediate => {
-                let value = self.mem_read(pc + 1);
+                let value = self.bus.peek(pc + 1);
                 format!("{} #${:02X}", mnemonic, value)
             }
             AddressingMode::ZeroPage => {
-                let addr = self.mem_read(pc + 1);
-                let value = self.mem_read(addr as u16);
+                let addr = self.bus.peek(pc + 1);
+                let value = self.bus.peek(addr as u16);
                 format!("{} ${:02X} = {:02X}", mnemonic, addr, value)
             }
             AddressingMode::ZeroPageX => {
-                let addr = self.mem_read(pc + 1);
+                let addr = self.bus.peek(pc + 1);
                 let effective = addr.wrapping_add(self.register_x);
-                let value = self.mem_read(effective as u16);
+                let value = self.bus.peek(effective as u16);
                 format!(
                     "{} ${:02X},X @ {:02X} = {:02X}",
                     mnemonic, addr, effective, value
                 )
             }
             AddressingMode::ZeroPageY => {
-                let addr = self.mem_read(pc + 1);
+                let addr = self.bus.peek(pc + 1);
                 let effective = addr.wrapping_add(self.register_y);
-                let value = self.mem_read(effective as u16);
+                let value = self.bus.peek(effective as u16);
                 format!(
                     "{} ${:02X},Y @ {:02X} = {:02X}",
                     mnemonic, addr, effective, value
                 )
             }
             AddressingMode::Absolute => {
-                let addr = self.mem_read_u16(pc + 1);
+                let addr = self.peek_u16(pc + 1);
                 if instruction.opcode == Opcode::JMP || instruction.opcode == Opcode::JSR {
                     format!("{} ${:04X}", mnemonic, addr)
                 } else {
-                    let value = self.mem_read(addr);
+                    let value = self.bus.peek(addr);
                     format!("{} ${:04X} = {:02X}", mnemonic, addr, value)
                 }
             }
             AddressingMode::AbsoluteX => {
-                let addr = self.mem_read_u16(pc + 1);
+                let addr = self.peek_u16(pc + 1);
                 let effective = addr.wrapping_add(self.register_x as u16);
-                let value = self.mem_read(effective);
+                let value = self.bus.peek(effective);
                 format!(
                     "{} ${:04X},X @ {:04X} = {:02X}",
                     mnemonic, addr, effective, value
                 )
             }
             AddressingMode::AbsoluteY => {
-                let addr = self.mem_read_u16(pc + 1);
+                let addr = self.peek_u16(pc + 1);
                 let effective = addr.wrapping_add(self.register_y as u16);
-                let value = self.mem_read(effective);
+                let value = self.bus.peek(effective);
                 format!(
                     "{} ${:04X},Y @ {:04X} = {:02X}",
                     mnemonic, addr, effective, value
                 )
             }
             AddressingMode::Indirect => {
-                let ptr = self.mem_read_u16(pc + 1);
+                let ptr = self.peek_u16(pc + 1);
                 if ptr & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(ptr) as u16;
-                    let hi = self.mem_read(ptr & 0xFF00) as u16;
+                    let lo = self.bus.peek(ptr) as u16;
+                    let hi = self.bus.peek(ptr & 0xFF00) as u16;
                     let addr = (hi << 8) | lo;
                     format!("{} (${:04X}) = {:04X}", mnemonic, ptr, addr)
                 } else {
-                    let addr = self.mem_read_u16(ptr);
+                    let addr = self.peek_u16(ptr);
                     format!("{} (${:04X}) = {:04X}", mnemonic, ptr, addr)
                 }
             }
             AddressingMode::IndirectX => {
-                let ptr = self.mem_read(pc + 1);
+                let ptr = self.bus.peek(pc + 1);
                 let ptr_addr = ptr.wrapping_add(self.register_x);
-                let addr = self.mem_read_u16(ptr_addr as u16);
-                let value = self.mem_read(addr);
+                let addr = self.peek_u16(ptr_addr as u16);
+                let value = self.bus.peek(addr);
                 format!(
                     "{} (${:02X},X) @ {:02X} = {:04X} = {:02X}",
                     mnemonic, ptr, ptr_addr, addr, value
                 )
             }
             AddressingMode::IndirectY => {
-                let ptr = self.mem_read(pc + 1);
-                let addr = self.mem_read_u16(ptr as u16);
+                let ptr = self.bus.peek(pc + 1);
+                let addr = self.peek_u16(ptr as u16);
                 let effective = addr.wrapping_add(self.register_y as u16);
-                let value = self.mem_read(effective);
+                let value = self.bus.peek(effective);
                 format!(
                     "{} (${:02X}),Y = {:04X} @ {:04X} = {:02X}",
                     mnemonic, ptr, addr, effective, value
                 )
             }
             AddressingMode::Relative => {
-                let offset = self.mem_read(pc + 1) as i8;
+                let offset = self.bus.peek(pc + 1) as i8;
                 let target = (pc as i32 + 2 + offset as i32) as u16;
                 format!("{} ${:04X}", mnemonic, target)
             }
         }
     }
 
+    fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.bus.peek(addr) as u16;
+        let hi = self.bus.peek(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
     fn decode(&self, opcode: u8) -> Instruction {
         opcodes::decode(opcode)
     }
@@ -259,15 +358,6 @@ impl CPU {
         self.set_flag(Flags::V, overflow);
     }
 
-    pub fn reset(&mut self) {
-        self.accumulator = 0;
-        self.register_x = 0;
-        self.register_y = 0;
-        self.stack_pointer = 0xFD;
-        self.status = 0x24;
-        self.program_counter = 0x8000;
-    }
-
     pub fn push(&mut self, val: u8) {
         self.mem_write(0x0100 | self.stack_pointer as u16, val);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
@@ -277,33 +367,513 @@ impl CPU {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
         self.mem_read(0x0100 | self.stack_pointer as u16)
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::interrupt::IrqSource;
+    use super::*;
+    use crate::cartridge;
+    use crate::joypad::Port;
+    use crate::rom::Rom;
 
-    pub fn load_irq_pc(&mut self) {
-        let high = self.mem_read(0xFFFF);
-        let low = self.mem_read(0xFFFE);
-        self.program_counter = (high as u16) << 8 | low as u16;
+    fn cpu_with_program(program: &[u8]) -> CPU {
+        cpu_with_segments(&[(0x8000, program)])
     }
 
-    pub fn irq(&mut self) {
-        if !self.get_flag(Flags::I) {
-            let high = (self.program_counter >> 8) as u8;
-            let low = (self.program_counter & 0xFF) as u8;
-            self.push(high);
-            self.push(low);
-            self.load_irq_pc();
-            self.push(self.status | 0x20);
-            self.set_flag(Flags::I, true);
-        } else {
+    fn cpu_with_segments(segments: &[(u16, &[u8])]) -> CPU {
+        // 16 KB PRG mirrored at $8000 and $C000 with vectors pointing at
+        // $8380 (NMI), $8000 (RESET) and $8400 (IRQ/BRK)
+        let mut prg = vec![0xEA; 0x4000];
+        for &(addr, bytes) in segments {
+            let start = (addr - 0x8000) as usize;
+            prg[start..start + bytes.len()].copy_from_slice(bytes);
         }
+        prg[0x3FFA] = 0x80;
+        prg[0x3FFB] = 0x83;
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+        prg[0x3FFE] = 0x00;
+        prg[0x3FFF] = 0x84;
+
+        let rom = Rom {
+            prg_rom: prg,
+            ..Default::default()
+        };
+        let mut cpu = CPU::new(Bus::new(cartridge::from_rom(rom).unwrap()));
+        cpu.reset();
+        cpu
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn test_lax_and_sax() {
+        let mut cpu = cpu_with_program(&[
+            0xA9, 0x81, // LDA #$81
+            0x85, 0x10, // STA $10
+            0xA9, 0x00, // LDA #$00
+            0xA7, 0x10, // LAX $10
+            0xA9, 0xF0, // LDA #$F0
+            0x87, 0x20, // SAX $20
+        ]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.accumulator, 0x81);
+        assert_eq!(cpu.register_x, 0x81);
+        assert!(cpu.get_flag(Flags::N));
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.mem_read(0x20), 0x80);
     }
 
-    pub fn nmi(&mut self) {
-        let high = (self.program_counter >> 8) as u8;
-        let low = (self.program_counter & 0xFF) as u8;
-        self.push(high);
-        self.push(low);
-        self.load_irq_pc();
-        self.push(self.status | 0x20);
-        self.set_flag(Flags::I, true);
+    #[test]
+    fn test_read_modify_write_combos() {
+        let mut cpu = cpu_with_program(&[
+            0xA9, 0x81, // LDA #$81
+            0x85, 0x10, // STA $10
+            0xC7, 0x10, // DCP $10
+            0x38, // SEC
+            0xE7, 0x10, // ISB $10
+            0x07, 0x10, // SLO $10
+        ]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.mem_read(0x10), 0x80);
+        assert!(cpu.get_flag(Flags::C));
+        assert!(!cpu.get_flag(Flags::Z));
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.mem_read(0x10), 0x81);
+        assert_eq!(cpu.accumulator, 0x00);
+        assert!(cpu.get_flag(Flags::Z));
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.accumulator, 0x02);
+        assert!(cpu.get_flag(Flags::C));
+    }
+
+    #[test]
+    fn test_immediate_combos() {
+        let mut cpu = cpu_with_program(&[
+            0xA9, 0xFF, // LDA #$FF
+            0x38, // SEC
+            0x6B, 0xC0, // ARR #$C0
+            0xA2, 0x25, // LDX #$25
+            0xCB, 0x20, // AXS #$20
+        ]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.accumulator, 0xE0);
+        assert!(cpu.get_flag(Flags::C));
+        assert!(!cpu.get_flag(Flags::V));
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register_x, 0x00);
+        assert!(cpu.get_flag(Flags::C));
+        assert!(cpu.get_flag(Flags::Z));
+    }
+
+    #[test]
+    fn test_trace_marks_unofficial_opcodes() {
+        let mut cpu = cpu_with_program(&[
+            0x04, 0x10, // NOP $10
+            0xEB, 0x01, // SBC #$01
+            0xEA, // NOP
+        ]);
+        assert!(cpu.trace().starts_with("8000  04 10    *NOP $10 = 00"));
+        run(&mut cpu, 1);
+        assert!(cpu.trace().starts_with("8002  EB 01    *SBC #$01"));
+        run(&mut cpu, 1);
+        assert!(cpu.trace().starts_with("8004  EA        NOP"));
+    }
+
+    #[test]
+    fn test_page_cross_cycles() {
+        let mut cpu = cpu_with_program(&[
+            0xA2, 0x20, // LDX #$20
+            0xBD, 0xF0, 0x00, // LDA $00F0,X (crosses into $0110)
+            0xBD, 0x00, 0x00, // LDA $0000,X
+            0x9D, 0x00, 0x00, // STA $0000,X
+            0x9D, 0xF0, 0x00, // STA $00F0,X
+        ]);
+        run(&mut cpu, 1);
+        let expected = [5, 4, 5, 5];
+        for cycles in expected {
+            let before = cpu.cycles;
+            run(&mut cpu, 1);
+            assert_eq!(cpu.cycles - before, cycles);
+        }
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut program = vec![
+            0xA9, 0x01, // LDA #$01
+            0xF0, 0x10, // BEQ (not taken)
+            0xD0, 0x00, // BNE +0 (taken, same page)
+            0x4C, 0xFD, 0x80, // JMP $80FD
+        ];
+        program.resize(0xFD, 0xEA);
+        program.extend([0xD0, 0x10]); // $80FD: BNE +$10 (taken, crosses to $810F)
+
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 1);
+        let expected = [2, 3, 3, 4];
+        for cycles in expected {
+            let before = cpu.cycles;
+            run(&mut cpu, 1);
+            assert_eq!(cpu.cycles - before, cycles);
+        }
+        assert_eq!(cpu.program_counter, 0x810F);
+    }
+
+    #[test]
+    fn test_cycle_core_matches_instruction_core() {
+        let main: &[u8] = &[
+            0xA2, 0x05, // LDX #$05
+            0xA0, 0x10, // LDY #$10
+            0xA9, 0x42, // LDA #$42
+            0x85, 0x20, // STA $20
+            0x95, 0x20, // STA $20,X
+            0x9D, 0xFE, 0x02, // STA $02FE,X
+            0xBD, 0xFE, 0x02, // LDA $02FE,X
+            0xB9, 0x00, 0x03, // LDA $0300,Y
+            0xA9, 0x00, // LDA #$00
+            0x85, 0x30, // STA $30
+            0xA9, 0x04, // LDA #$04
+            0x85, 0x31, // STA $31
+            0xB1, 0x30, // LDA ($30),Y
+            0x91, 0x30, // STA ($30),Y
+            0xA1, 0x2B, // LDA ($2B,X)
+            0xFE, 0x00, 0x04, // INC $0400,X
+            0x1E, 0xFF, 0x04, // ASL $04FF,X
+            0xDF, 0x00, 0x04, // DCP $0400,X
+            0xF3, 0x30, // ISB ($30),Y
+            0x07, 0x20, // SLO $20
+            0x6F, 0x00, 0x04, // RRA $0400
+            0x0A, // ASL A
+            0x20, 0x00, 0x81, // JSR $8100
+            0x48, // PHA
+            0x08, // PHP
+            0x68, // PLA
+            0x28, // PLP
+            0xA9, 0x00, // LDA #$00
+            0x85, 0x40, // STA $40
+            0xA9, 0x82, // LDA #$82
+            0x85, 0x41, // STA $41
+            0x6C, 0x40, 0x00, // JMP ($0040)
+        ];
+        let sub: &[u8] = &[
+            0xA9, 0x99, // LDA #$99
+            0x60, // RTS
+        ];
+        let loop_and_brk: &[u8] = &[
+            0xA2, 0x03, // LDX #$03
+            0xCA, // DEX
+            0xD0, 0xFD, // BNE -3
+            0x00, 0xEA, // BRK
+            0x4C, 0xFD, 0x82, // JMP $82FD
+        ];
+        let crossing_branch: &[u8] = &[
+            0xF0, 0x10, // BEQ +$10 (to $830F)
+        ];
+        let handler: &[u8] = &[
+            0x40, // RTI
+        ];
+        let segments = [
+            (0x8000, main),
+            (0x8100, sub),
+            (0x8200, loop_and_brk),
+            (0x82FD, crossing_branch),
+            (0x8400, handler),
+        ];
+
+        let mut instruction_cpu = cpu_with_segments(&segments);
+        let mut cycle_cpu = cpu_with_segments(&segments);
+        cycle_cpu.set_execution_mode(ExecutionMode::Cycle);
+
+        while instruction_cpu.program_counter != 0x8310 {
+            assert_eq!(instruction_cpu.trace(), cycle_cpu.trace());
+            instruction_cpu.step();
+            cycle_cpu.step();
+        }
+        assert_eq!(instruction_cpu.trace(), cycle_cpu.trace());
+        for addr in 0x0000..0x0800 {
+            assert_eq!(instruction_cpu.mem_read(addr), cycle_cpu.mem_read(addr));
+        }
+    }
+
+    #[test]
+    fn test_cycle_core_ticks_per_instruction() {
+        let mut cpu = cpu_with_program(&[
+            0xEE, 0x00, 0x02, // INC $0200
+            0xBD, 0xFF, 0x02, // LDA $02FF,X
+        ]);
+        cpu.set_execution_mode(ExecutionMode::Cycle);
+        cpu.register_x = 1;
+        let start = cpu.cycles;
+
+        let mut ticks = 0;
+        loop {
+            cpu.tick();
+            ticks += 1;
+            if cpu.at_instruction_boundary() {
+                break;
+            }
+        }
+        assert_eq!(ticks, 6);
+        assert_eq!(cpu.mem_read(0x0200), 1);
+
+        cpu.step();
+        assert_eq!(cpu.cycles - start, 6 + 5);
+    }
+
+    fn for_each_mode(test: impl Fn(ExecutionMode)) {
+        test(ExecutionMode::Instruction);
+        test(ExecutionMode::Cycle);
+    }
+
+    #[test]
+    fn test_reset_reads_vector() {
+        let cpu = cpu_with_segments(&[]);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.cycles, 7);
+        assert!(cpu.get_flag(Flags::I));
+    }
+
+    #[test]
+    fn test_cli_delays_irq_by_one_instruction() {
+        for_each_mode(|mode| {
+            let mut cpu = cpu_with_program(&[
+                0x58, // CLI
+                0xEA, // NOP
+                0xEA, // NOP
+            ]);
+            cpu.set_execution_mode(mode);
+            cpu.bus.set_irq_line(IrqSource::External, true);
+
+            cpu.step();
+            assert_eq!(cpu.program_counter, 0x8001);
+            let before = cpu.cycles;
+            cpu.step();
+            assert_eq!(cpu.program_counter, 0x8400);
+            assert_eq!(cpu.cycles - before, 2 + 7);
+            assert!(cpu.get_flag(Flags::I));
+            // Return address and status with B clear
+            assert_eq!(cpu.mem_read(0x01FD), 0x80);
+            assert_eq!(cpu.mem_read(0x01FC), 0x02);
+            assert_eq!(cpu.mem_read(0x01FB) & 0x30, 0x20);
+        });
+    }
+
+    #[test]
+    fn test_sei_takes_pending_irq() {
+        for_each_mode(|mode| {
+            let mut cpu = cpu_with_program(&[
+                0x58, // CLI
+                0xEA, // NOP
+                0x78, // SEI
+                0xEA, // NOP
+            ]);
+            cpu.set_execution_mode(mode);
+            run(&mut cpu, 2);
+            cpu.bus.set_irq_line(IrqSource::External, true);
+
+            cpu.step();
+            assert_eq!(cpu.program_counter, 0x8400);
+            // The pushed status already has I set
+            assert_ne!(cpu.mem_read(0x01FB) & Flags::I as u8, 0);
+        });
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        for_each_mode(|mode| {
+            let mut cpu = cpu_with_segments(&[(0x8380, &[0xEA, 0x40])]); // NOP; RTI
+            cpu.set_execution_mode(mode);
+            cpu.bus.set_nmi_line(true);
+
+            cpu.step();
+            assert_eq!(cpu.program_counter, 0x8380);
+            run(&mut cpu, 2);
+            assert_eq!(cpu.program_counter, 0x8001);
+            // The line is still high, but without a new edge there is no NMI
+            run(&mut cpu, 2);
+            assert_eq!(cpu.program_counter, 0x8003);
+        });
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut cpu = cpu_with_program(&[0x00, 0xEA]); // BRK
+        cpu.set_execution_mode(ExecutionMode::Cycle);
+
+        cpu.tick();
+        cpu.bus.set_nmi_line(true);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8380);
+        // BRK still pushes its return address and the B flag
+        assert_eq!(cpu.mem_read(0x01FC), 0x02);
+        assert_ne!(cpu.mem_read(0x01FB) & Flags::B as u8, 0);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_irq() {
+        let mut cpu = cpu_with_program(&[0x58, 0xEA, 0xEA]); // CLI; NOP
+        cpu.set_execution_mode(ExecutionMode::Cycle);
+        cpu.bus.set_irq_line(IrqSource::External, true);
+        run(&mut cpu, 1);
+
+        // Run the NOP, then the first cycles of the IRQ sequence
+        for _ in 0..2 + 3 {
+            cpu.tick();
+        }
+        cpu.bus.set_nmi_line(true);
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8380);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        for_each_mode(|mode| {
+            // The DMA takes an extra alignment cycle when it halts the CPU
+            // on an odd cycle
+            for (program, setup, stall) in [
+                (&[0xA9, 0x03, 0x8D, 0x14, 0x40][..], 1, 514), // LDA #$03; STA $4014
+                (&[0xA5, 0x00, 0xA9, 0x03, 0x8D, 0x14, 0x40][..], 2, 513), // LDA $00; ...
+            ] {
+                let mut cpu = cpu_with_program(program);
+                cpu.set_execution_mode(mode);
+                for i in 0..=0xFF {
+                    cpu.mem_write(0x0300 + i, i as u8 ^ 0x5A);
+                }
+                run(&mut cpu, setup);
+                let before = cpu.cycles;
+                cpu.step();
+                assert_eq!(cpu.cycles - before, 4 + stall);
+                assert!(cpu.trace().ends_with(&format!("CYC:{}", cpu.cycles)));
+
+                for i in 0..=0xFF {
+                    cpu.mem_write(0x2003, i);
+                    // Attribute bytes don't store bits 2-4
+                    let mask = if i & 0x03 == 0x02 { 0xE3 } else { 0xFF };
+                    assert_eq!(cpu.mem_read(0x2004), (i ^ 0x5A) & mask);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        for_each_mode(|mode| {
+            let mut cpu = cpu_with_segments(&[
+                (
+                    0x8000,
+                    &[
+                        0xA9, 0x80, // LDA #$80
+                        0x8D, 0x00, 0x20, // STA $2000
+                        0x4C, 0x05, 0x80, // JMP $8005
+                    ],
+                ),
+                (0x8380, &[0xE6, 0x10, 0x40]), // INC $10; RTI
+            ]);
+            cpu.set_execution_mode(mode);
+            // Vblank starts 241 lines and a dot into the frame, which the
+            // PPU begins at the first instruction
+            let vblank = 7 + (241 * 341 + 1) / 3;
+            while cpu.cycles < vblank - 10 {
+                cpu.step();
+            }
+            assert_eq!(cpu.mem_read(0x10), 0);
+            while cpu.cycles < vblank + 40 {
+                cpu.step();
+            }
+            assert_eq!(cpu.mem_read(0x10), 1);
+            while cpu.cycles < vblank + 29781 + 40 {
+                cpu.step();
+            }
+            assert_eq!(cpu.mem_read(0x10), 2);
+        });
+    }
+
+    #[test]
+    fn test_dmc_dma() {
+        for_each_mode(|mode| {
+            // The fetch realigns to a get cycle like OAM DMA
+            for (prefix, stall) in [(&[][..], 3), (&[0xA5, 0x00][..], 4)] {
+                let mut program = prefix.to_vec();
+                program.extend([
+                    0xA9, 0x8F, // LDA #$8F
+                    0x8D, 0x10, 0x40, // STA $4010 (IRQ, fastest rate)
+                    0xA9, 0x10, // LDA #$10
+                    0x8D, 0x15, 0x40, // STA $4015 (1-byte sample at $C000)
+                    0x58, // CLI
+                ]);
+                let mut cpu = cpu_with_program(&program);
+                cpu.set_execution_mode(mode);
+                run(&mut cpu, prefix.len() / 2 + 4);
+                assert!(!cpu.bus.irq_line());
+
+                // The sample fetch halts the CLI's first read, then the
+                // finished sample raises the DMC IRQ
+                let before = cpu.cycles;
+                cpu.step();
+                assert_eq!(cpu.cycles - before, 2 + stall);
+                assert!(cpu.bus.irq_line());
+                cpu.step();
+                assert_eq!(cpu.program_counter, 0x8400);
+            }
+        });
+    }
+
+    #[test]
+    fn test_dmc_dma_controller_glitch() {
+        let mut cpu = cpu_with_program(&[]);
+        cpu.bus.joypad_mut(Port::One).set_buttons(0x02); // B
+        cpu.mem_write(0x4016, 1);
+        cpu.mem_write(0x4016, 0);
+        cpu.mem_write(0x4015, 0x10);
+
+        // The repeated read during the fetch shifts out A unseen
+        assert_eq!(cpu.mem_read(0x4016), 1);
+        assert_eq!(cpu.mem_read(0x4016), 0);
+    }
+
+    #[test]
+    fn test_oam_dma_with_dmc_fetch() {
+        let oam_dma_cycles = |dmc: bool| {
+            let mut cpu = cpu_with_program(&[]);
+            if dmc {
+                cpu.bus.mem_write(0x4015, 0x10);
+            }
+            cpu.bus.mem_write(0x4014, 0x02);
+            let start = cpu.cycles;
+            cpu.run_pending_dma();
+            assert!(cpu.bus.dmc_dma_address().is_none());
+            cpu.cycles - start
+        };
+        // The DMC fetch takes one of the OAM read cycles and one to realign
+        assert_eq!(oam_dma_cycles(true), oam_dma_cycles(false) + 2);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut cpu = cpu_with_segments(&[
+            (0x8000, &[0x58, 0x4C, 0x01, 0x80]), // CLI; JMP $8001
+            (0x8400, &[0xAD, 0x15, 0x40, 0x40]), // LDA $4015; RTI
+        ]);
+        while cpu.program_counter != 0x8400 {
+            cpu.step();
+        }
+        // The flag is set 29828 cycles into the 4-step sequence, then the
+        // JMP under way finishes and the IRQ sequence takes 7 cycles
+        let irq_cycle = 7 + 29828;
+        assert!((irq_cycle + 7..=irq_cycle + 3 + 7).contains(&cpu.cycles));
+        run(&mut cpu, 2);
+        assert_eq!(cpu.accumulator & 0x40, 0x40);
+        assert!(!cpu.bus.irq_line());
     }
 }
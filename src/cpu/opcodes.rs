@@ -1,5 +1,3 @@
-use std::ops::Add;
-
 use super::types::{AddressingMode, Instruction, Opcode};
 
 pub fn decode(opcode: u8) -> Instruction {
@@ -862,6 +860,516 @@ pub fn decode(opcode: u8) -> Instruction {
             addressing_mode: AddressingMode::AbsoluteX,
             cycles: 7,
         },
+
+        // Unofficial opcodes (marked with `*` in traces)
+
+        // NOP (unofficial)
+        0x1A => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0x3A => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0x5A => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0x7A => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0xDA => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0xFA => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Implied,
+            cycles: 2,
+        },
+        0x80 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0x82 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0x89 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0xC2 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0xE2 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0x04 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 3,
+        },
+        0x44 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 3,
+        },
+        0x64 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 3,
+        },
+        0x14 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0x34 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0x54 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0x74 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0xD4 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0xF4 => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 4,
+        },
+        0x0C => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 4,
+        },
+        0x1C => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+        0x3C => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+        0x5C => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+        0x7C => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+        0xDC => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+        0xFC => Instruction {
+            opcode: Opcode::NOP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 4,
+        },
+
+        // LAX variants
+        0xA3 => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 6,
+        },
+        0xA7 => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 3,
+        },
+        0xAF => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 4,
+        },
+        0xB3 => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 5,
+        },
+        0xB7 => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::ZeroPageY,
+            cycles: 4,
+        },
+        0xBF => Instruction {
+            opcode: Opcode::LAX,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 4,
+        },
+
+        // LXA (unstable)
+        0xAB => Instruction {
+            opcode: Opcode::LXA,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // SAX variants
+        0x83 => Instruction {
+            opcode: Opcode::SAX,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 6,
+        },
+        0x87 => Instruction {
+            opcode: Opcode::SAX,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 3,
+        },
+        0x8F => Instruction {
+            opcode: Opcode::SAX,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 4,
+        },
+        0x97 => Instruction {
+            opcode: Opcode::SAX,
+            addressing_mode: AddressingMode::ZeroPageY,
+            cycles: 4,
+        },
+
+        // SBC (unofficial)
+        0xEB => Instruction {
+            opcode: Opcode::SBC,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // DCP variants
+        0xC3 => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0xC7 => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0xCF => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0xD3 => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0xD7 => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0xDB => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0xDF => Instruction {
+            opcode: Opcode::DCP,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // ISB variants
+        0xE3 => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0xE7 => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0xEF => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0xF3 => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0xF7 => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0xFB => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0xFF => Instruction {
+            opcode: Opcode::ISB,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // SLO variants
+        0x03 => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0x07 => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0x0F => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0x13 => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0x17 => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0x1B => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0x1F => Instruction {
+            opcode: Opcode::SLO,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // RLA variants
+        0x23 => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0x27 => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0x2F => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0x33 => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0x37 => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0x3B => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0x3F => Instruction {
+            opcode: Opcode::RLA,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // SRE variants
+        0x43 => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0x47 => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0x4F => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0x53 => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0x57 => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0x5B => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0x5F => Instruction {
+            opcode: Opcode::SRE,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // RRA variants
+        0x63 => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::IndirectX,
+            cycles: 8,
+        },
+        0x67 => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::ZeroPage,
+            cycles: 5,
+        },
+        0x6F => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::Absolute,
+            cycles: 6,
+        },
+        0x73 => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 8,
+        },
+        0x77 => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::ZeroPageX,
+            cycles: 6,
+        },
+        0x7B => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 7,
+        },
+        0x7F => Instruction {
+            opcode: Opcode::RRA,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 7,
+        },
+
+        // ANC variants
+        0x0B => Instruction {
+            opcode: Opcode::ANC,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+        0x2B => Instruction {
+            opcode: Opcode::ANC,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // ALR
+        0x4B => Instruction {
+            opcode: Opcode::ALR,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // ARR
+        0x6B => Instruction {
+            opcode: Opcode::ARR,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // AXS
+        0xCB => Instruction {
+            opcode: Opcode::AXS,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // XAA (unstable)
+        0x8B => Instruction {
+            opcode: Opcode::XAA,
+            addressing_mode: AddressingMode::Immediate,
+            cycles: 2,
+        },
+
+        // SHA (unstable)
+        0x93 => Instruction {
+            opcode: Opcode::SHA,
+            addressing_mode: AddressingMode::IndirectY,
+            cycles: 6,
+        },
+        0x9F => Instruction {
+            opcode: Opcode::SHA,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 5,
+        },
+
+        // SHX (unstable)
+        0x9E => Instruction {
+            opcode: Opcode::SHX,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 5,
+        },
+
+        // SHY (unstable)
+        0x9C => Instruction {
+            opcode: Opcode::SHY,
+            addressing_mode: AddressingMode::AbsoluteX,
+            cycles: 5,
+        },
+
+        // TAS (unstable)
+        0x9B => Instruction {
+            opcode: Opcode::TAS,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 5,
+        },
+
+        // LAS
+        0xBB => Instruction {
+            opcode: Opcode::LAS,
+            addressing_mode: AddressingMode::AbsoluteY,
+            cycles: 4,
+        },
+
         _ => Instruction {
             opcode: Opcode::Unknown,
             addressing_mode: AddressingMode::Indirect,
@@ -869,3 +1377,32 @@ pub fn decode(opcode: u8) -> Instruction {
         },
     }
 }
+
+/// Returns true for opcodes outside the documented 6502 instruction set,
+/// including the unofficial NOP encodings and the $EB alias of SBC.
+pub fn is_unofficial(opcode: u8) -> bool {
+    match decode(opcode).opcode {
+        Opcode::NOP => opcode != 0xEA,
+        Opcode::SBC => opcode == 0xEB,
+        Opcode::LAX
+        | Opcode::LXA
+        | Opcode::SAX
+        | Opcode::DCP
+        | Opcode::ISB
+        | Opcode::SLO
+        | Opcode::RLA
+        | Opcode::SRE
+        | Opcode::RRA
+        | Opcode::ANC
+        | Opcode::ALR
+        | Opcode::ARR
+        | Opcode::AXS
+        | Opcode::XAA
+        | Opcode::SHA
+        | Opcode::SHX
+        | Opcode::SHY
+        | Opcode::TAS
+        | Opcode::LAS => true,
+        _ => false,
+    }
+}
@@ -65,9 +65,95 @@ pub enum Opcode {
 
     // Other
     NOP,
+
+    // Unofficial combined operations
+    LAX, SAX, DCP, ISB, SLO, RLA, SRE, RRA,
+
+    // Unofficial immediate operations
+    ANC, ALR, ARR, AXS,
+
+    // Unofficial unstable operations
+    LXA, XAA, SHA, SHX, SHY, TAS, LAS,
+
     Unknown,
 }
 
+/// How an instruction uses its memory operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    None,
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+impl Opcode {
+    pub fn access(&self) -> Access {
+        match self {
+            Opcode::LDA
+            | Opcode::LDX
+            | Opcode::LDY
+            | Opcode::ADC
+            | Opcode::SBC
+            | Opcode::AND
+            | Opcode::EOR
+            | Opcode::ORA
+            | Opcode::CMP
+            | Opcode::CPX
+            | Opcode::CPY
+            | Opcode::BIT
+            | Opcode::NOP
+            | Opcode::LAX
+            | Opcode::LAS
+            | Opcode::ANC
+            | Opcode::ALR
+            | Opcode::ARR
+            | Opcode::AXS
+            | Opcode::LXA
+            | Opcode::XAA => Access::Read,
+            Opcode::STA
+            | Opcode::STX
+            | Opcode::STY
+            | Opcode::SAX
+            | Opcode::SHA
+            | Opcode::SHX
+            | Opcode::SHY
+            | Opcode::TAS => Access::Write,
+            Opcode::ASL
+            | Opcode::LSR
+            | Opcode::ROL
+            | Opcode::ROR
+            | Opcode::INC
+            | Opcode::DEC
+            | Opcode::SLO
+            | Opcode::RLA
+            | Opcode::SRE
+            | Opcode::RRA
+            | Opcode::DCP
+            | Opcode::ISB => Access::ReadModifyWrite,
+            _ => Access::None,
+        }
+    }
+
+    /// Read instructions take an extra cycle in their indexed forms when the
+    /// effective address crosses a page; stores and read-modify-write
+    /// instructions always spend that cycle.
+    pub fn is_read(&self) -> bool {
+        self.access() == Access::Read
+    }
+}
+
+/// Selects how `CPU::step` advances the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// Executes a whole instruction at once and adds its cycles afterwards.
+    #[default]
+    Instruction,
+    /// Executes one bus access per `CPU::tick`, including the dummy reads and
+    /// writes of the real 6502.
+    Cycle,
+}
+
 pub struct Instruction {
     pub opcode: Opcode,
     pub addressing_mode: AddressingMode,
@@ -0,0 +1,219 @@
+//! Built-in database of known dumps, used to fix wrong iNES header fields.
+
+use crate::hash;
+use crate::rom::{Mirroring, Rom, Timing};
+
+/// Corrected board configuration for one dump. `None` fields keep whatever
+/// the header says.
+pub struct GameDbEntry {
+    pub name: &'static str,
+    /// CRC32 of PRG+CHR, without header or trainer.
+    pub crc32: u32,
+    /// SHA-1 of PRG+CHR as lowercase hex, or empty when only the CRC is known.
+    pub sha1: &'static str,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    /// Total PRG-RAM size, battery-backed if the board has a battery.
+    pub prg_ram_size: Option<usize>,
+    pub timing: Option<Timing>,
+}
+
+impl GameDbEntry {
+    /// An entry that overrides nothing, to build entries with `..EMPTY`.
+    pub const EMPTY: GameDbEntry = GameDbEntry {
+        name: "",
+        crc32: 0,
+        sha1: "",
+        mapper: None,
+        submapper: None,
+        mirroring: None,
+        battery: None,
+        prg_ram_size: None,
+        timing: None,
+    };
+}
+
+/// Dumps known to circulate with bad headers. Only add entries whose hashes
+/// were computed from a verified dump.
+pub(crate) const GAME_DB: &[GameDbEntry] = &[];
+
+/// Finds the entry for `rom` in the built-in database.
+pub fn lookup(rom: &Rom) -> Option<&'static GameDbEntry> {
+    lookup_in(GAME_DB, rom)
+}
+
+/// Finds the entry for `rom` in `db`.
+pub fn lookup_in<'a>(db: &'a [GameDbEntry], rom: &Rom) -> Option<&'a GameDbEntry> {
+    let crc32 = rom.crc32();
+    let mut sha1 = None;
+    db.iter().find(|entry| {
+        // Only hash with SHA-1 when a CRC matches and the entry has one
+        entry.crc32 == crc32
+            && (entry.sha1.is_empty()
+                || *sha1.get_or_insert_with(|| hash::to_hex(&rom.sha1())) == entry.sha1)
+    })
+}
+
+/// Overrides the header fields of `rom` with `entry` and logs every field
+/// that actually changed. Returns the log lines.
+pub fn apply(rom: &mut Rom, entry: &GameDbEntry) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if let Some(mapper) = entry.mapper
+        && mapper != rom.mapper
+    {
+        changes.push(format!("mapper {} -> {}", rom.mapper, mapper));
+        rom.mapper = mapper;
+    }
+    if let Some(submapper) = entry.submapper
+        && submapper != rom.submapper
+    {
+        changes.push(format!("submapper {} -> {}", rom.submapper, submapper));
+        rom.submapper = submapper;
+    }
+    if let Some(mirroring) = entry.mirroring
+        && mirroring != rom.mirroring
+    {
+        changes.push(format!("mirroring {:?} -> {:?}", rom.mirroring, mirroring));
+        rom.mirroring = mirroring;
+    }
+    if let Some(battery) = entry.battery
+        && battery != rom.battery
+    {
+        changes.push(format!("battery {} -> {}", rom.battery, battery));
+        rom.battery = battery;
+    }
+    let prg_ram_size = rom.prg_ram_size + rom.prg_nvram_size;
+    if let Some(size) = entry.prg_ram_size
+        && size != prg_ram_size
+    {
+        changes.push(format!("PRG-RAM {} -> {} bytes", prg_ram_size, size));
+    }
+    let prg_ram_size = entry.prg_ram_size.unwrap_or(prg_ram_size);
+    (rom.prg_ram_size, rom.prg_nvram_size) = if rom.battery {
+        (0, prg_ram_size)
+    } else {
+        (prg_ram_size, 0)
+    };
+    if let Some(timing) = entry.timing
+        && timing != rom.timing
+    {
+        changes.push(format!("timing {:?} -> {:?}", rom.timing, timing));
+        rom.timing = timing;
+    }
+
+    for change in &changes {
+        eprintln!(
+            "INFO: Game database override for {}: {}",
+            entry.name, change
+        );
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::RomError;
+
+    /// Matches `test_image`.
+    const TEST_DB: &[GameDbEntry] = &[GameDbEntry {
+        name: "Test Image",
+        crc32: 0x3E4A_9AD6,
+        sha1: "f3765a60151f1515669c38c9f4e526d3b76fb63f",
+        mapper: Some(1),
+        mirroring: Some(Mirroring::Vertical),
+        battery: Some(true),
+        ..GameDbEntry::EMPTY
+    }];
+
+    /// iNES image claiming mapper 0 with horizontal mirroring, no battery.
+    fn test_image(flags7: u8) -> Vec<u8> {
+        let mut image = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, flags7];
+        image.resize(16, 0);
+        image.extend([0xA5; 0x4000]);
+        image.extend([0x5A; 0x2000]);
+        image
+    }
+
+    fn rom() -> Rom {
+        Rom {
+            prg_rom: vec![0x42; 0x4000],
+            chr_rom: vec![0x24; 0x2000],
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: 0x2000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_by_hashes() {
+        let rom = rom();
+        let sha1 = hash::to_hex(&rom.sha1()).leak();
+        let db = [
+            GameDbEntry {
+                name: "Wrong SHA-1",
+                crc32: rom.crc32(),
+                sha1: "0000000000000000000000000000000000000000",
+                ..GameDbEntry::EMPTY
+            },
+            GameDbEntry {
+                name: "Match",
+                crc32: rom.crc32(),
+                sha1,
+                ..GameDbEntry::EMPTY
+            },
+        ];
+        assert_eq!(lookup_in(&db, &rom).map(|entry| entry.name), Some("Match"));
+        assert!(lookup_in(&db[..1], &rom).is_none());
+        assert!(lookup(&rom).is_none());
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let mut rom = rom();
+        let entry = GameDbEntry {
+            name: "Test",
+            mapper: Some(1),
+            mirroring: Some(Mirroring::Horizontal),
+            battery: Some(true),
+            ..GameDbEntry::EMPTY
+        };
+
+        let changes = apply(&mut rom, &entry);
+
+        assert_eq!(changes, ["mapper 0 -> 1", "battery false -> true"]);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_lookup_from_rom_parsing() -> Result<(), RomError> {
+        let rom = Rom::new_with_db(&test_image(0x00), TEST_DB)?;
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        // The built-in database doesn't know the test image
+        assert_eq!(Rom::new(&test_image(0x00))?.mapper, 0);
+
+        // NES 2.0 headers skip the database; the same lookup then reports
+        // what was logged for the iNES image
+        let mut nes2 = Rom::new_with_db(&test_image(0x08), TEST_DB)?;
+        assert_eq!(nes2.mapper, 0);
+        let entry = lookup_in(TEST_DB, &nes2).expect("test image is in the database");
+        assert_eq!(
+            apply(&mut nes2, entry),
+            [
+                "mapper 0 -> 1",
+                "mirroring Horizontal -> Vertical",
+                "battery false -> true",
+            ]
+        );
+        Ok(())
+    }
+}
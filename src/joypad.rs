@@ -0,0 +1,62 @@
+pub const BUTTON_A: u8 = 1 << 0;
+pub const BUTTON_B: u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START: u8 = 1 << 3;
+pub const BUTTON_UP: u8 = 1 << 4;
+pub const BUTTON_DOWN: u8 = 1 << 5;
+pub const BUTTON_LEFT: u8 = 1 << 6;
+pub const BUTTON_RIGHT: u8 = 1 << 7;
+
+/// One of the two controller ports, read through $4016 and $4017.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+/// Standard controller: an 8-bit shift register read one button at a time
+/// through $4016/$4017, in A, B, Select, Start, Up, Down, Left, Right order.
+pub struct Joypad {
+    strobe: bool,
+    index: u8,
+    buttons: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            strobe: false,
+            index: 0,
+            buttons: 0,
+        }
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.index > 7 {
+            // Official controllers report 1 once all buttons are shifted out
+            return 1;
+        }
+        let bit = (self.buttons >> self.index) & 1;
+        if !self.strobe {
+            self.index += 1;
+        }
+        bit
+    }
+}
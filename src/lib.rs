@@ -0,0 +1,312 @@
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod gamedb;
+pub mod hash;
+pub mod joypad;
+pub mod nsf;
+pub mod ppu;
+pub mod region;
+pub mod rom;
+pub mod wav;
+
+use bus::Bus;
+use cpu::CPU;
+use cpu::types::ExecutionMode;
+use joypad::Port;
+use region::Region;
+use rom::{Rom, RomError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Battery RAM is written back to the save file this often (about 5 s).
+const SAVE_FLUSH_FRAMES: u32 = 300;
+
+/// A complete console: the CPU and the bus it owns, which in turn holds the
+/// RAM, PPU, APU, controllers and the cartridge.
+pub struct Nes {
+    cpu: CPU,
+    save: Option<SaveFile>,
+}
+
+/// Where battery RAM is persisted, and what was last written there.
+struct SaveFile {
+    path: PathBuf,
+    saved: Vec<u8>,
+    frames_since_flush: u32,
+}
+
+impl Nes {
+    pub fn from_rom(raw: &[u8]) -> Result<Nes, RomError> {
+        let rom = Rom::new(raw)?;
+        let region = Region::from_timing(rom.timing);
+        let cartridge = cartridge::from_rom(rom)?;
+        let mut bus = Bus::new(cartridge);
+        bus.set_region(region);
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        Ok(Nes { cpu, save: None })
+    }
+
+    /// The conventional save file for a ROM: same name, `.sav` extension.
+    pub fn save_path_for(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sav")
+    }
+
+    /// Persists battery-backed RAM in `path`. The file is loaded now if it
+    /// exists, then written back every few seconds of emulation and when
+    /// the console is dropped. Does nothing for carts without a battery.
+    pub fn attach_save_file(&mut self, path: PathBuf) -> io::Result<()> {
+        let Some(ram) = self.cpu.bus_mut().cartridge_mut().battery_ram_mut() else {
+            return Ok(());
+        };
+        match fs::read(&path) {
+            Ok(data) => {
+                let len = data.len().min(ram.len());
+                ram[..len].copy_from_slice(&data[..len]);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        self.save = Some(SaveFile {
+            path,
+            saved: ram.to_vec(),
+            frames_since_flush: 0,
+        });
+        Ok(())
+    }
+
+    /// Writes battery RAM to the save file if it changed since the last write.
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        let (Some(save), Some(ram)) = (&mut self.save, self.cpu.bus().cartridge().battery_ram())
+        else {
+            return Ok(());
+        };
+        save.frames_since_flush = 0;
+        if save.saved == ram {
+            return Ok(());
+        }
+        // Write to a temporary file first so a crash can't truncate the save
+        let temp = save.path.with_extension("sav.tmp");
+        fs::write(&temp, ram)?;
+        fs::rename(&temp, &save.path)?;
+        save.saved = ram.to_vec();
+        Ok(())
+    }
+
+    /// Battery-backed RAM of the cartridge, if it has any.
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        self.cpu.bus().cartridge().battery_ram()
+    }
+
+    /// Console region, detected from the ROM header or game database.
+    pub fn region(&self) -> Region {
+        self.cpu.bus().region()
+    }
+
+    /// Overrides the detected region, e.g. to run a European release whose
+    /// header doesn't say so at PAL speed.
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus_mut().set_region(region);
+    }
+
+    /// Runs one instruction (plus any interrupt it triggers) and returns the
+    /// CPU cycles it took.
+    pub fn step_instruction(&mut self) -> u64 {
+        let before = self.cpu.cycles();
+        self.cpu.step();
+        self.cpu.cycles() - before
+    }
+
+    /// Runs until the PPU finishes the picture it is drawing, so
+    /// `framebuffer` holds a complete frame.
+    pub fn run_frame(&mut self) {
+        let frame = self.cpu.bus().ppu().frame_count();
+        while self.cpu.bus().ppu().frame_count() == frame {
+            self.cpu.step();
+        }
+
+        if let Some(save) = &mut self.save {
+            save.frames_since_flush += 1;
+            if save.frames_since_flush >= SAVE_FLUSH_FRAMES
+                && let Err(err) = self.flush_save()
+            {
+                eprintln!("WARNING: Failed to write save file: {}", err);
+            }
+        }
+    }
+
+    /// Palette indices of the last completed frame, 256x240, row by row.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.bus().ppu().framebuffer()
+    }
+
+    /// Takes the audio samples produced since the last call: mono, at the
+    /// rate set with `set_sample_rate` (44100 Hz by default).
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus_mut().apu_mut().drain_samples()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus_mut().apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Also resamples each APU channel on its own, for `audio_stems`.
+    pub fn set_audio_stems(&mut self, enabled: bool) {
+        self.cpu.bus_mut().apu_mut().set_stems_enabled(enabled);
+    }
+
+    /// Takes each channel's samples produced since the last call, in
+    /// `apu::CHANNEL_NAMES` order. `None` unless stems are enabled.
+    pub fn audio_stems(&mut self) -> Option<[Vec<f32>; 5]> {
+        self.cpu.bus_mut().apu_mut().drain_stems()
+    }
+
+    /// Sets the pressed buttons of the controller in `port` as a mask of the
+    /// `joypad::BUTTON_*` bits.
+    pub fn set_buttons(&mut self, port: Port, state: u8) {
+        self.cpu.bus_mut().joypad_mut(port).set_buttons(state);
+    }
+
+    /// Read-only view of the CPU registers.
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Jumps to `pc`, e.g. to start nestest in its automated mode at $C000.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu.set_pc(pc);
+    }
+
+    pub fn set_execution_mode(&mut self, mode: ExecutionMode) {
+        self.cpu.set_execution_mode(mode);
+    }
+
+    /// nestest-style trace line for the next instruction.
+    pub fn trace(&self) -> String {
+        self.cpu.trace()
+    }
+}
+
+impl Drop for Nes {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save() {
+            eprintln!("WARNING: Failed to write save file: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nes_with_program(program: &[u8]) -> Nes {
+        nes_with_header(program, 0x00)
+    }
+
+    fn nes_with_header(program: &[u8], flags6: u8) -> Nes {
+        Nes::from_rom(&rom_image(program, flags6)).unwrap()
+    }
+
+    fn rom_image(program: &[u8], flags6: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, flags6, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        raw
+    }
+
+    #[test]
+    fn test_controller_read() {
+        let mut nes = nes_with_program(&[
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0xAE, 0x16, 0x40, // LDX $4016
+        ]);
+        nes.set_buttons(Port::One, joypad::BUTTON_B | joypad::BUTTON_START);
+        for _ in 0..6 {
+            nes.step_instruction();
+        }
+        assert_eq!(nes.cpu().accumulator(), 0);
+        assert_eq!(nes.cpu().register_x(), 1);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut nes = nes_with_program(&[0x4C, 0x00, 0x80]); // JMP $8000
+        assert_eq!(nes.cpu().program_counter(), 0x8000);
+        nes.run_frame();
+        assert_eq!(nes.framebuffer().len(), 256 * 240);
+
+        // Frames follow the PPU: 29780.67 cycles on average, with no drift
+        let start = nes.cpu().cycles();
+        for _ in 0..30 {
+            nes.run_frame();
+        }
+        let cycles = (nes.cpu().cycles() - start) as f64;
+        assert!((cycles - 30.0 * 89342.0 / 3.0).abs() < 4.0, "{}", cycles);
+    }
+
+    #[test]
+    fn test_region_detection() {
+        let mut raw = rom_image(&[0x4C, 0x00, 0x80], 0x00); // JMP $8000
+        raw[7] = 0x08; // NES 2.0
+        raw[12] = 0x01; // PAL
+        let mut nes = Nes::from_rom(&raw).unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+        nes.run_frame();
+        let start = nes.cpu().cycles();
+        nes.run_frame();
+        let cycles = nes.cpu().cycles() - start;
+        assert!(cycles.abs_diff(Region::Pal.cpu_cycles_per_frame()) < 4);
+
+        // Switching regions takes effect from the next frame
+        nes.set_region(Region::Ntsc);
+        assert_eq!(nes.region(), Region::Ntsc);
+        nes.run_frame();
+        let start = nes.cpu().cycles();
+        nes.run_frame();
+        let cycles = nes.cpu().cycles() - start;
+        assert!(cycles.abs_diff(Region::Ntsc.cpu_cycles_per_frame()) < 4);
+    }
+
+    #[test]
+    fn test_battery_save_file() {
+        let path = std::env::temp_dir().join(format!("nurst-test-{}.sav", std::process::id()));
+        fs::write(&path, [0x11, 0x22]).unwrap();
+
+        let program = [
+            0xAD, 0x00, 0x60, // LDA $6000
+            0x8D, 0x01, 0x60, // STA $6001
+            0xA9, 0x33, // LDA #$33
+            0x8D, 0xFF, 0x7F, // STA $7FFF
+        ];
+        let mut nes = nes_with_header(&program, 0x02);
+        nes.attach_save_file(path.clone()).unwrap();
+        for _ in 0..4 {
+            nes.step_instruction();
+        }
+        nes.flush_save().unwrap();
+
+        let saved = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.len(), 0x2000);
+        assert_eq!(&saved[..2], [0x11, 0x11]);
+        assert_eq!(saved[0x1FFF], 0x33);
+
+        let mut no_battery = nes_with_program(&program);
+        no_battery.attach_save_file(path.clone()).unwrap();
+        no_battery.flush_save().unwrap();
+        assert!(no_battery.battery_ram().is_none());
+        assert!(!path.exists());
+    }
+}
@@ -1,27 +1,274 @@
-mod bus;
-mod cpu;
-mod rom;
-
-use cpu::CPU;
-use ppu::PPU;
-use rom::Rom;
+use nurst::Nes;
+use nurst::apu::{CHANNEL_NAMES, DEFAULT_SAMPLE_RATE, SAMPLE_RATES};
+use nurst::nsf::{self, Nsf, NsfPlayer};
+use nurst::rom::Rom;
+use nurst::wav::WavWriter;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: nurst [ROM|NSF] [--headless] [--frames N] [--record-audio OUT.wav] \
+                     [--audio-stems] [--sample-rate HZ] [--track N] [--seconds S]";
+
+/// NSF tracks without a length in the file play this long, in milliseconds.
+const DEFAULT_TRACK_LENGTH: u32 = 150_000;
+const DEFAULT_FADE: u32 = 5_000;
+/// Longest track, and longest fade, rendered. Both together at the highest
+/// sample rate stay well within the WAV size limit.
+const MAX_SECONDS: u32 = 3_600;
+
+/// Command line options. Without `--headless` (or `--record-audio`, which
+/// implies it) the ROM is run as nestest and traced. NSF files are always
+/// rendered to WAV.
+struct Options {
+    rom_path: PathBuf,
+    headless: bool,
+    frames: u32,
+    record_audio: Option<PathBuf>,
+    audio_stems: bool,
+    sample_rate: u32,
+    /// NSF song to render, 1-based.
+    track: Option<u8>,
+    /// Overrides the NSF track length.
+    seconds: Option<u32>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            rom_path: PathBuf::from("nestest.nes"),
+            headless: false,
+            frames: 600,
+            record_audio: None,
+            audio_stems: false,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            track: None,
+            seconds: None,
+        };
+        let mut rom_path = None;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--frames" => {
+                    options.frames = parse_number(&value("--frames")?)?;
+                }
+                "--record-audio" => {
+                    options.record_audio = Some(PathBuf::from(value("--record-audio")?));
+                    options.headless = true;
+                }
+                "--audio-stems" => options.audio_stems = true,
+                "--sample-rate" => {
+                    let rate = parse_number(&value("--sample-rate")?)?;
+                    if !SAMPLE_RATES.contains(&rate) {
+                        return Err(format!(
+                            "--sample-rate must be {}-{} Hz",
+                            SAMPLE_RATES.start(),
+                            SAMPLE_RATES.end()
+                        ));
+                    }
+                    options.sample_rate = rate;
+                }
+                "--track" => {
+                    let track = parse_number(&value("--track")?)?;
+                    let track = u8::try_from(track).map_err(|_| "--track is too large")?;
+                    options.track = Some(track);
+                }
+                "--seconds" => {
+                    let seconds = parse_number(&value("--seconds")?)?;
+                    if !(1..=MAX_SECONDS).contains(&seconds) {
+                        return Err(format!("--seconds must be 1-{MAX_SECONDS}"));
+                    }
+                    options.seconds = Some(seconds);
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+                _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+        if options.audio_stems && options.record_audio.is_none() {
+            return Err("--audio-stems needs --record-audio".to_string());
+        }
+        if let Some(path) = rom_path {
+            options.rom_path = path;
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{value} is not a valid number"))
+}
 
 fn main() {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    });
+
+    let data = fs::read(&options.rom_path).expect("Failed to read ROM");
+    if nsf::is_nsf(&data) {
+        render_nsf(&data, &options);
+    } else if options.headless {
+        run_headless(&data, &options);
+    } else {
+        run_nestest(&data, &options.rom_path);
+    }
+}
+
+/// Runs `options.frames` frames without output, recording audio if asked.
+/// Battery RAM is loaded from and saved to the ROM's `.sav` file.
+fn run_headless(rom_data: &[u8], options: &Options) {
+    let mut nes = Nes::from_rom(rom_data).expect("Failed to parse ROM");
+    if let Err(err) = nes.attach_save_file(Nes::save_path_for(&options.rom_path)) {
+        eprintln!("WARNING: Failed to read save file: {}", err);
+    }
+    nes.set_sample_rate(options.sample_rate);
+    nes.set_audio_stems(options.audio_stems);
+
+    let mut recorder = options
+        .record_audio
+        .as_deref()
+        .map(|path| AudioRecorder::create(path, options.sample_rate, options.audio_stems));
+
+    for _ in 0..options.frames {
+        nes.run_frame();
+        if let Some(recorder) = &mut recorder {
+            recorder.write(&nes.audio_samples(), nes.audio_stems().as_ref());
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
+    println!("Ran {} frames", options.frames);
+}
+
+/// WAV files for the mixed output and, optionally, one per APU channel
+/// next to it (`out.wav` gets `out.pulse1.wav` and so on).
+struct AudioRecorder {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<WavWriter<BufWriter<File>>>,
+}
+
+impl AudioRecorder {
+    fn create(path: &Path, sample_rate: u32, stems: bool) -> AudioRecorder {
+        let create = |path: &Path| {
+            WavWriter::create(path, sample_rate).expect("Failed to create audio file")
+        };
+        let stems = if stems {
+            CHANNEL_NAMES
+                .iter()
+                .map(|name| create(&path.with_extension(format!("{name}.wav"))))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        AudioRecorder {
+            mix: create(path),
+            stems,
+        }
+    }
+
+    fn write(&mut self, mix: &[f32], stems: Option<&[Vec<f32>; 5]>) {
+        self.mix.write_samples(mix).expect("Failed to write audio");
+        for (stem, samples) in self.stems.iter_mut().zip(stems.into_iter().flatten()) {
+            stem.write_samples(samples).expect("Failed to write audio");
+        }
+    }
+
+    fn finish(self) {
+        for wav in std::iter::once(self.mix).chain(self.stems) {
+            wav.finish().expect("Failed to write audio");
+        }
+    }
+}
+
+/// Renders one NSF track to `--record-audio`, or next to the NSF, fading
+/// out at the end.
+fn render_nsf(data: &[u8], options: &Options) {
+    let nsf = Nsf::new(data).unwrap_or_else(|err| {
+        eprintln!("Failed to parse NSF: {err}");
+        process::exit(1);
+    });
+    let song = match options.track {
+        Some(track) if (1..=nsf.songs).contains(&track) => track - 1,
+        Some(track) => {
+            eprintln!("Track {track} is out of range 1-{}", nsf.songs);
+            process::exit(2);
+        }
+        None => nsf.starting_song,
+    };
+    let track = &nsf.tracks[song as usize];
+    println!("{} - {} ({})", nsf.name, nsf.artist, nsf.copyright);
+    println!(
+        "Track {}/{}: {}",
+        song + 1,
+        nsf.songs,
+        track.name.as_deref().unwrap_or("")
+    );
+
+    let length = options
+        .seconds
+        .map(|seconds| seconds * 1000)
+        .or(track.length)
+        .unwrap_or(DEFAULT_TRACK_LENGTH)
+        .min(MAX_SECONDS * 1000);
+    let fade = track.fade.unwrap_or(DEFAULT_FADE).min(MAX_SECONDS * 1000);
+    let to_samples = |ms: u32| (ms as u64 * options.sample_rate as u64 / 1000) as usize;
+    let (fade_start, total) = (to_samples(length), to_samples(length + fade));
+    let gain = |position: usize| {
+        let faded = position.saturating_sub(fade_start) as f32;
+        1.0 - faded / (total - fade_start).max(1) as f32
+    };
+
+    let mut player = NsfPlayer::new(&nsf, song);
+    player.set_sample_rate(options.sample_rate);
+    player.set_audio_stems(options.audio_stems);
+    let default_path = options.rom_path.with_extension("wav");
+    let path = options.record_audio.as_deref().unwrap_or(&default_path);
+    let mut recorder = AudioRecorder::create(path, options.sample_rate, options.audio_stems);
+
+    let mut position = 0;
+    while position < total {
+        player.run_play_period();
+        let mut mix = player.audio_samples();
+        let mut stems = player.audio_stems();
+        let len = mix.len().min(total - position);
+        for samples in std::iter::once(&mut mix).chain(stems.iter_mut().flatten()) {
+            samples.truncate(len);
+            for (offset, sample) in samples.iter_mut().enumerate() {
+                *sample *= gain(position + offset);
+            }
+        }
+        recorder.write(&mix, stems.as_ref());
+        position += len;
+    }
+    recorder.finish();
+    println!(
+        "Wrote {:.1} s to {}",
+        total as f64 / options.sample_rate as f64,
+        path.display()
+    );
+}
+
+fn run_nestest(rom_data: &[u8], rom_path: &Path) {
     // Load the nestest ROM
-    let rom_data = fs::read("nestest.nes").expect("Failed to read nestest.nes");
-    let rom = Rom::new(&rom_data).expect("Failed to parse ROM");
+    let rom = Rom::new(rom_data).expect("Failed to parse ROM");
 
     println!("ROM loaded successfully!");
     println!("PRG ROM size: {} bytes", rom.prg_rom.len());
     println!("CHR ROM size: {} bytes", rom.chr_rom.len());
+    println!("PRG+CHR CRC32: {:08X}", rom.crc32());
 
-    let mut cpu = CPU::new();
-    cpu.load(&rom.prg_rom);
-    cpu.reset();
+    let mut nes = Nes::from_rom(rom_data).expect("Failed to parse ROM");
+    nes.attach_save_file(Nes::save_path_for(rom_path))
+        .expect("Failed to read save file");
 
-    cpu.set_pc(0xC000);
+    nes.set_pc(0xC000);
 
     let mut log_file = File::create("my_nestest.log").expect("Failed to create log file");
 
@@ -29,10 +276,10 @@ fn main() {
 
     let max_instructions = 10000; // Safety limit
     for _ in 0..max_instructions {
-        let trace = cpu.trace();
+        let trace = nes.trace();
         writeln!(log_file, "{}", trace).expect("Failed to write to log");
 
-        cpu.step();
+        nes.step_instruction();
     }
 
     println!("Nestest execution complete!");
@@ -0,0 +1,415 @@
+//! NSF and NSFe music rips: the game's sound driver and data, plus the
+//! addresses to call to start and advance a song.
+
+mod player;
+
+use crate::rom::Timing;
+use std::error::Error;
+use std::fmt;
+
+pub use player::NsfPlayer;
+
+const NSF_TAG: [u8; 5] = *b"NESM\x1A";
+const NSFE_TAG: [u8; 4] = *b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+
+/// Play rates in microseconds used when the file gives none.
+const DEFAULT_NTSC_SPEED: u16 = 16_639;
+const DEFAULT_PAL_SPEED: u16 = 19_997;
+
+/// Why a file could not be loaded as an NSF or NSFe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NsfError {
+    /// Shorter than the NSF header.
+    TooShort,
+    /// Starts with neither "NESM\x1A" nor "NSFE".
+    BadMagic,
+    /// The file ends inside a chunk.
+    TruncatedChunk,
+    /// A required NSFe chunk is absent or too short.
+    MissingChunk(&'static str),
+    /// An NSFe chunk the player must understand but doesn't.
+    UnsupportedChunk(String),
+    /// The program is empty.
+    NoData,
+    /// A non-bankswitched program that doesn't load into $8000-$FFFF.
+    BadLoadAddress(u16),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NsfError::TooShort => write!(f, "file is too short for an NSF header"),
+            NsfError::BadMagic => write!(f, "file is not in NSF or NSFe format"),
+            NsfError::TruncatedChunk => write!(f, "file ends inside an NSFe chunk"),
+            NsfError::MissingChunk(id) => write!(f, "missing {} chunk", id),
+            NsfError::UnsupportedChunk(id) => write!(f, "unsupported required chunk {}", id),
+            NsfError::NoData => write!(f, "file contains no program data"),
+            NsfError::BadLoadAddress(addr) => write!(f, "bad load address ${:04X}", addr),
+        }
+    }
+}
+
+impl Error for NsfError {}
+
+/// Per-song metadata from NSFe chunks. Times are in milliseconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub name: Option<String>,
+    pub length: Option<u32>,
+    /// Fade-out after `length`.
+    pub fade: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct Nsf {
+    pub name: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Who made the rip (NSFe only).
+    pub ripper: String,
+    /// Number of songs, at least 1.
+    pub songs: u8,
+    /// Song to play first, 0-based.
+    pub starting_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    /// Microseconds between PLAY calls on NTSC and PAL consoles.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    /// Banks mapped to $8000-$FFFF at INIT. All zero if the program isn't
+    /// bankswitched.
+    pub bank_init: [u8; 8],
+    pub timing: Timing,
+    /// Expansion sound chips (VRC6, FDS, MMC5, ...) as a bit mask.
+    pub expansion: u8,
+    pub data: Vec<u8>,
+    /// One entry per song.
+    pub tracks: Vec<Track>,
+}
+
+/// Decodes the region byte shared by NSF and NSFe: bit 0 PAL, bit 1 both.
+fn timing(flags: u8) -> Timing {
+    if flags & 0b10 != 0 {
+        Timing::MultiRegion
+    } else if flags & 0b01 != 0 {
+        Timing::Pal
+    } else {
+        Timing::Ntsc
+    }
+}
+
+/// NUL-terminated (or padded) text.
+fn text(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+fn u16_at(raw: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([raw[offset], raw[offset + 1]])
+}
+
+/// An NSFe chunk's ID and payload.
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Splits NSFe-style chunks (length, ID, payload) up to "NEND" or the end
+/// of `raw`.
+fn chunks(mut raw: &[u8]) -> Result<Vec<Chunk<'_>>, NsfError> {
+    let mut chunks = Vec::new();
+    while !raw.is_empty() {
+        if raw.len() < 8 {
+            return Err(NsfError::TruncatedChunk);
+        }
+        let len = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize;
+        let id = [raw[4], raw[5], raw[6], raw[7]];
+        let payload = raw.get(8..8 + len).ok_or(NsfError::TruncatedChunk)?;
+        if &id == b"NEND" {
+            break;
+        }
+        chunks.push((id, payload));
+        raw = &raw[8 + len..];
+    }
+    Ok(chunks)
+}
+
+/// Reads the little-endian millisecond times of a "time" or "fade" chunk;
+/// negative entries mean the player's default.
+fn times(payload: &[u8]) -> impl Iterator<Item = Option<u32>> + '_ {
+    payload
+        .chunks_exact(4)
+        .map(|t| u32::try_from(i32::from_le_bytes([t[0], t[1], t[2], t[3]])).ok())
+}
+
+/// Whether `raw` looks like an NSF or NSFe rather than a cartridge image.
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(&NSF_TAG) || raw.starts_with(&NSFE_TAG)
+}
+
+impl Nsf {
+    pub fn new(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = if raw.starts_with(&NSF_TAG) {
+            Nsf::from_nsf(raw)?
+        } else if raw.starts_with(&NSFE_TAG) {
+            Nsf::from_nsfe(raw)?
+        } else if raw.len() < NSFE_TAG.len() {
+            return Err(NsfError::TooShort);
+        } else {
+            return Err(NsfError::BadMagic);
+        };
+
+        if nsf.data.is_empty() {
+            return Err(NsfError::NoData);
+        }
+        if !nsf.is_bankswitched() && nsf.load_address < 0x8000 {
+            return Err(NsfError::BadLoadAddress(nsf.load_address));
+        }
+        nsf.songs = nsf.songs.max(1);
+        if nsf.starting_song >= nsf.songs {
+            nsf.starting_song = 0;
+        }
+        nsf.tracks.resize(nsf.songs as usize, Track::default());
+        if nsf.ntsc_speed == 0 {
+            nsf.ntsc_speed = DEFAULT_NTSC_SPEED;
+        }
+        if nsf.pal_speed == 0 {
+            nsf.pal_speed = DEFAULT_PAL_SPEED;
+        }
+        Ok(nsf)
+    }
+
+    fn from_nsf(raw: &[u8]) -> Result<Nsf, NsfError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(NsfError::TooShort);
+        }
+        let mut nsf = Nsf {
+            songs: raw[0x06],
+            starting_song: raw[0x07].saturating_sub(1),
+            load_address: u16_at(raw, 0x08),
+            init_address: u16_at(raw, 0x0A),
+            play_address: u16_at(raw, 0x0C),
+            name: text(&raw[0x0E..0x2E]),
+            artist: text(&raw[0x2E..0x4E]),
+            copyright: text(&raw[0x4E..0x6E]),
+            ntsc_speed: u16_at(raw, 0x6E),
+            bank_init: raw[0x70..0x78].try_into().unwrap(),
+            pal_speed: u16_at(raw, 0x78),
+            timing: timing(raw[0x7A]),
+            expansion: raw[0x7B],
+            ..Default::default()
+        };
+
+        // NSF2 can give the program length, followed by NSFe metadata
+        let version = raw[0x05];
+        let data_len = u32::from_le_bytes([raw[0x7D], raw[0x7E], raw[0x7F], 0]) as usize;
+        let body = &raw[HEADER_SIZE..];
+        if version >= 2 && data_len > 0 && data_len < body.len() {
+            nsf.data = body[..data_len].to_vec();
+            for (id, payload) in chunks(&body[data_len..])? {
+                nsf.read_metadata(id, payload)?;
+            }
+        } else {
+            nsf.data = body.to_vec();
+        }
+        Ok(nsf)
+    }
+
+    fn from_nsfe(raw: &[u8]) -> Result<Nsf, NsfError> {
+        let mut nsf = Nsf::default();
+        let mut has_info = false;
+        for (id, payload) in chunks(&raw[NSFE_TAG.len()..])? {
+            match &id {
+                b"INFO" => {
+                    if payload.len() < 9 {
+                        return Err(NsfError::MissingChunk("INFO"));
+                    }
+                    nsf.load_address = u16_at(payload, 0);
+                    nsf.init_address = u16_at(payload, 2);
+                    nsf.play_address = u16_at(payload, 4);
+                    nsf.timing = timing(payload[6]);
+                    nsf.expansion = payload[7];
+                    nsf.songs = payload[8];
+                    nsf.starting_song = payload.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => nsf.data = payload.to_vec(),
+                b"BANK" => {
+                    let len = payload.len().min(8);
+                    nsf.bank_init[..len].copy_from_slice(&payload[..len]);
+                }
+                b"RATE" => {
+                    if payload.len() >= 2 {
+                        nsf.ntsc_speed = u16_at(payload, 0);
+                    }
+                    if payload.len() >= 4 {
+                        nsf.pal_speed = u16_at(payload, 2);
+                    }
+                }
+                _ => nsf.read_metadata(id, payload)?,
+            }
+        }
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        Ok(nsf)
+    }
+
+    /// Applies an NSFe metadata chunk. Chunks with a lowercase first letter
+    /// are optional and skipped when unknown.
+    fn read_metadata(&mut self, id: [u8; 4], payload: &[u8]) -> Result<(), NsfError> {
+        match &id {
+            b"auth" => {
+                let mut fields = payload.split(|&b| b == 0).map(text);
+                for field in [
+                    &mut self.name,
+                    &mut self.artist,
+                    &mut self.copyright,
+                    &mut self.ripper,
+                ] {
+                    match fields.next() {
+                        Some(value) => *field = value,
+                        None => break,
+                    }
+                }
+            }
+            b"tlbl" => {
+                let names = payload.split(|&b| b == 0).map(text);
+                for (song, name) in names.enumerate().take(u8::MAX as usize) {
+                    self.track_mut(song).name = Some(name);
+                }
+            }
+            b"time" => {
+                for (song, length) in times(payload).enumerate().take(u8::MAX as usize) {
+                    self.track_mut(song).length = length;
+                }
+            }
+            b"fade" => {
+                for (song, fade) in times(payload).enumerate().take(u8::MAX as usize) {
+                    self.track_mut(song).fade = fade;
+                }
+            }
+            _ if id[0].is_ascii_uppercase() => {
+                return Err(NsfError::UnsupportedChunk(text(&id)));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn track_mut(&mut self, song: usize) -> &mut Track {
+        if self.tracks.len() <= song {
+            self.tracks.resize(song + 1, Track::default());
+        }
+        &mut self.tracks[song]
+    }
+
+    /// Whether the program is switched in 4 KB banks through $5FF8-$5FFF.
+    pub fn is_bankswitched(&self) -> bool {
+        self.bank_init.iter().any(|&bank| bank != 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = (payload.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(payload);
+        chunk
+    }
+
+    #[test]
+    fn test_nsf_header() {
+        let mut raw = vec![0; HEADER_SIZE];
+        raw[..5].copy_from_slice(&NSF_TAG);
+        raw[0x05] = 1;
+        raw[0x06] = 3; // 3 songs
+        raw[0x07] = 2; // starting with the second
+        raw[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        raw[0x0E..0x13].copy_from_slice(b"Title");
+        raw[0x6E..0x70].copy_from_slice(&16_666u16.to_le_bytes());
+        raw[0x7A] = 0b01; // PAL
+        raw.extend([0x60; 16]);
+
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(nsf.name, "Title");
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(
+            (nsf.load_address, nsf.init_address, nsf.play_address),
+            (0x8000, 0x8003, 0x8006)
+        );
+        assert_eq!((nsf.ntsc_speed, nsf.pal_speed), (16_666, DEFAULT_PAL_SPEED));
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert!(!nsf.is_bankswitched());
+        assert_eq!(nsf.data.len(), 16);
+        assert_eq!(nsf.tracks, vec![Track::default(); 3]);
+
+        assert_eq!(Nsf::new(&raw[..0x40]).unwrap_err(), NsfError::TooShort);
+        assert_eq!(Nsf::new(b"NES\x1A").unwrap_err(), NsfError::BadMagic);
+        raw[0x09] = 0x60; // non-bankswitched below $8000
+        assert_eq!(
+            Nsf::new(&raw).unwrap_err(),
+            NsfError::BadLoadAddress(0x6000)
+        );
+    }
+
+    #[test]
+    fn test_nsfe_chunks() {
+        let mut raw = NSFE_TAG.to_vec();
+        raw.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0b10, 0, 2],
+        ));
+        raw.extend(chunk(b"BANK", &[0, 1]));
+        raw.extend(chunk(b"DATA", &[0x60; 8]));
+        raw.extend(chunk(b"auth", b"Game\0Composer\0\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"Intro\0Boss\0"));
+        raw.extend(chunk(
+            b"time",
+            &[(-1i32).to_le_bytes(), 90_000i32.to_le_bytes()].concat(),
+        ));
+        raw.extend(chunk(b"fade", &5_000i32.to_le_bytes()));
+        raw.extend(chunk(b"xtra", &[1, 2, 3]));
+        raw.extend(chunk(b"NEND", &[]));
+        raw.extend(chunk(b"JUNK", &[]));
+
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(nsf.name, "Game");
+        assert_eq!(nsf.artist, "Composer");
+        assert_eq!(nsf.copyright, "");
+        assert_eq!(nsf.ripper, "Ripper");
+        assert_eq!(nsf.timing, Timing::MultiRegion);
+        assert_eq!(nsf.ntsc_speed, DEFAULT_NTSC_SPEED);
+        assert!(nsf.is_bankswitched());
+        assert_eq!(nsf.data, [0x60; 8]);
+        assert_eq!(
+            nsf.tracks,
+            [
+                Track {
+                    name: Some("Intro".to_string()),
+                    length: None,
+                    fade: Some(5_000),
+                },
+                Track {
+                    name: Some("Boss".to_string()),
+                    length: Some(90_000),
+                    fade: None,
+                },
+            ]
+        );
+
+        // Unknown chunks are an error only when they start with a capital
+        let end = raw.len() - 16;
+        raw.truncate(end);
+        raw.extend(chunk(b"VRC7", &[]));
+        assert_eq!(
+            Nsf::new(&raw).unwrap_err(),
+            NsfError::UnsupportedChunk("VRC7".to_string())
+        );
+        raw.truncate(end - 4);
+        assert_eq!(Nsf::new(&raw).unwrap_err(), NsfError::TruncatedChunk);
+    }
+}
@@ -0,0 +1,187 @@
+use super::Nsf;
+use crate::bus::Bus;
+use crate::cartridge::{self, NSF_DRIVER_START};
+use crate::cpu::{CPU, Mem};
+use crate::region::Region;
+
+/// Entry points of the driver written to the board's driver RAM.
+const INIT_CALL: u16 = NSF_DRIVER_START;
+const IDLE: u16 = NSF_DRIVER_START + 7;
+const PLAY_CALL: u16 = NSF_DRIVER_START + 10;
+
+/// Plays one song of an NSF: INIT runs once, then PLAY is called at the
+/// rate the file asks for. Nothing drives the PPU's NMI, so the timing
+/// comes from the CPU cycle count alone.
+pub struct NsfPlayer {
+    cpu: CPU,
+    /// CPU cycles between PLAY calls.
+    play_period: f64,
+    next_play: f64,
+}
+
+impl NsfPlayer {
+    /// Powers up the board for `nsf` and starts `song` (0-based).
+    pub fn new(nsf: &Nsf, song: u8) -> NsfPlayer {
+        if nsf.expansion != 0 {
+            eprintln!(
+                "WARNING: NSF uses expansion audio chips ({:#04X}), which are not emulated",
+                nsf.expansion
+            );
+        }
+        let region = Region::from_timing(nsf.timing);
+        let mut bus = Bus::new(cartridge::from_nsf(nsf));
+        bus.set_region(region);
+
+        let [init_lo, init_hi] = nsf.init_address.to_le_bytes();
+        let [play_lo, play_hi] = nsf.play_address.to_le_bytes();
+        let [idle_lo, idle_hi] = IDLE.to_le_bytes();
+        let ntsc_pal = (region == Region::Pal) as u8;
+        #[rustfmt::skip]
+        let driver = [
+            0xA9, song,                  // LDA #song
+            0xA2, ntsc_pal,              // LDX #region
+            0x20, init_lo, init_hi,      // JSR INIT
+            0x4C, idle_lo, idle_hi,      // idle: JMP idle
+            0x20, play_lo, play_hi,      // JSR PLAY
+            0x4C, idle_lo, idle_hi,      // JMP idle
+        ];
+        for (addr, byte) in (INIT_CALL..).zip(driver) {
+            bus.mem_write(addr, byte);
+        }
+
+        // Silence the APU the way the NSF spec asks before INIT
+        for addr in 0x4000..=0x4013 {
+            bus.mem_write(addr, 0x00);
+        }
+        bus.mem_write(0x4015, 0x00);
+        bus.mem_write(0x4015, 0x0F);
+        bus.mem_write(0x4017, 0x40);
+
+        let speed = match region {
+            Region::Pal => nsf.pal_speed,
+            Region::Ntsc | Region::Dendy => nsf.ntsc_speed,
+        };
+        let play_period = speed as f64 * region.cpu_clock_rate() / 1_000_000.0;
+        let mut cpu = CPU::new(bus);
+        cpu.set_pc(INIT_CALL);
+        NsfPlayer {
+            cpu,
+            play_period,
+            next_play: play_period,
+        }
+    }
+
+    /// Runs until the next PLAY call is due and makes it. A call is skipped
+    /// if INIT or the previous PLAY hasn't returned yet.
+    pub fn run_play_period(&mut self) {
+        while (self.cpu.cycles() as f64) < self.next_play {
+            self.cpu.step();
+        }
+        self.next_play += self.play_period;
+        if self.cpu.program_counter() == IDLE {
+            self.cpu.set_pc(PLAY_CALL);
+        }
+    }
+
+    /// Takes the audio samples produced since the last call, like
+    /// `Nes::audio_samples`.
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus_mut().apu_mut().drain_samples()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus_mut().apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Also resamples each APU channel on its own, for `audio_stems`.
+    pub fn set_audio_stems(&mut self, enabled: bool) {
+        self.cpu.bus_mut().apu_mut().set_stems_enabled(enabled);
+    }
+
+    /// Like `Nes::audio_stems`.
+    pub fn audio_stems(&mut self) -> Option<[Vec<f32>; 5]> {
+        self.cpu.bus_mut().apu_mut().drain_stems()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Timing;
+
+    /// INIT stores A and X, PLAY counts its calls and keeps pulse 1 going.
+    fn counter_nsf(bank_init: [u8; 8]) -> Nsf {
+        #[rustfmt::skip]
+        let program = [
+            // INIT at $8000
+            0x85, 0x00,       // STA $00
+            0x86, 0x01,       // STX $01
+            0xA9, 0xBF,       // LDA #$BF
+            0x8D, 0x00, 0x40, // STA $4000
+            0xA9, 0xFD,       // LDA #$FD
+            0x8D, 0x02, 0x40, // STA $4002
+            0x8D, 0x03, 0x40, // STA $4003
+            0x60,             // RTS
+            // PLAY at $8012
+            0xE6, 0x02,       // INC $02
+            0xAD, 0x00, 0x90, // LDA $9000
+            0x85, 0x03,       // STA $03
+            0x60,             // RTS
+        ];
+        let mut data = program.to_vec();
+        data.resize(0x2000, 0);
+        data[0x1000] = 0x11; // $9000 in bank 1
+        Nsf {
+            songs: 4,
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8012,
+            ntsc_speed: 16_639,
+            pal_speed: 19_997,
+            bank_init,
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn ram(player: &mut NsfPlayer, addr: u16) -> u8 {
+        player.cpu.bus_mut().mem_read(addr)
+    }
+
+    #[test]
+    fn test_init_and_play() {
+        let mut player = NsfPlayer::new(&counter_nsf([0; 8]), 2);
+        let mut samples = Vec::new();
+        for _ in 0..60 {
+            player.run_play_period();
+            samples.extend(player.audio_samples());
+        }
+        assert_eq!(ram(&mut player, 0x00), 2);
+        assert_eq!(ram(&mut player, 0x01), 0);
+        // The first call comes one period in, so 59 have run
+        assert_eq!(ram(&mut player, 0x02), 59);
+        assert_eq!(ram(&mut player, 0x03), 0x11);
+
+        let cycles = player.cpu.cycles() as f64;
+        assert!((cycles - 60.0 * player.play_period).abs() < 8.0);
+        assert!(samples.len().abs_diff(44_100) < 100, "{}", samples.len());
+        assert!(samples.iter().any(|s| s.abs() > 0.05));
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        let mut nsf = counter_nsf([0, 0, 0, 0, 0, 0, 0, 1]);
+        nsf.timing = Timing::Pal;
+        let mut player = NsfPlayer::new(&nsf, 0);
+        // Bank 0 is now also behind $9000, and X says PAL
+        player.run_play_period();
+        player.run_play_period();
+        assert_eq!(ram(&mut player, 0x01), 1);
+        assert_eq!(ram(&mut player, 0x03), 0x85);
+
+        player.cpu.bus_mut().mem_write(0x5FF9, 1);
+        player.run_play_period();
+        assert_eq!(ram(&mut player, 0x03), 0x11);
+        assert_eq!(ram(&mut player, 0xF000), 0x11);
+    }
+}
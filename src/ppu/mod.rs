@@ -1,23 +1,74 @@
-use crate::bus::Bus;
+mod render;
+
+use crate::cartridge::{Mapper, Nametable};
+use crate::region::Region;
+use render::{Background, Sprites};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// PPUCTRL ($2000)
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_8X16: u8 = 0b0010_0000;
+const CTRL_NMI: u8 = 0b1000_0000;
+
+// PPUMASK ($2001)
+const MASK_GREYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_SHOW_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SHOW_SPRITES: u8 = 0b0001_0000;
+
+// PPUSTATUS ($2002)
+const STATUS_SPRITE_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE0_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
 
 pub struct PPU {
     ctrl: u8,
     mask: u8,
     status: u8,
     oam_addr: u8,
-    oam_data: u8,
-    scroll: u8,
-    addr: u8,
+    /// PPUDATA read buffer.
     data: u8,
-    oam_dma: u8,
+    /// Last value driven on the CPU-facing data bus, returned by reads of
+    /// write-only registers and the low bits of PPUSTATUS.
+    open_bus: u8,
     oam: [u8; 256],
-    vram: [u8; 2000],
+    /// The console's 2 KB of nametable RAM (CIRAM).
+    vram: [u8; 2048],
     palette_mem: [u8; 32],
+    /// Current VRAM address (15 bits): fine Y, nametable, coarse Y, coarse X.
     v: u16,
+    /// Fine X scroll (3 bits).
     x: u8,
+    /// Temporary VRAM address, copied into `v` during rendering.
     t: u16,
+    /// Write toggle shared by PPUSCROLL and PPUADDR.
     w: bool,
-    cycles: u8,
+    region: Region,
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    /// Set by a PPUSTATUS read on the dot before vblank starts, which keeps
+    /// the flag from being set that frame.
+    vblank_suppressed: bool,
+    frame_count: u64,
+    bg: Background,
+    sprites: Sprites,
+    /// Frame being drawn.
+    pixels: Vec<u8>,
+    /// Last completed frame.
+    frame: Vec<u8>,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PPU {
@@ -27,49 +78,685 @@ impl PPU {
             mask: 0,
             status: 0,
             oam_addr: 0,
-            oam_data: 0,
-            scroll: 0,
-            addr: 0,
             data: 0,
-            oam_dma: u8,
-            vram: [0; 2000],
+            open_bus: 0,
+            oam: [0; 256],
+            vram: [0; 2048],
             palette_mem: [0; 32],
             v: 0,
             x: 0,
             t: 0,
-            w: 0,
-            cycles: 0,
+            w: false,
+            region: Region::default(),
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            vblank_suppressed: false,
+            frame_count: 0,
+            bg: Background::default(),
+            sprites: Sprites::default(),
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    /// Switches the frame layout. Takes effect from the next scanline.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Palette indices of the last rendered frame, row by row.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Number of frames completed so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Level of the PPU's NMI output: vblank is set and PPUCTRL enables NMI.
+    ///
+    /// The CPU samples the line late in its cycle, so a flag set during the
+    /// first dots of vblank is not seen until the next cycle; a PPUSTATUS
+    /// read in that window clears it before the NMI can happen.
+    pub fn nmi_line(&self) -> bool {
+        self.ctrl & CTRL_NMI != 0
+            && self.status & STATUS_VBLANK != 0
+            && !(self.scanline == self.region.vblank_line() && self.dot <= 3)
+    }
+
+    fn v_increment(&self) -> u16 {
+        if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
         }
     }
-    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+
+    /// Palette RAM index for `addr`. The backdrop entries of the sprite
+    /// palettes ($3F10/$14/$18/$1C) mirror those of the background.
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1F) as usize;
+        if index & 0x13 == 0x10 {
+            index & 0x0F
+        } else {
+            index
+        }
+    }
+
+    fn read_palette(&self, addr: u16) -> u8 {
+        let value = self.palette_mem[Self::palette_index(addr)];
+        if self.mask & MASK_GREYSCALE != 0 {
+            value & 0x30
+        } else {
+            value
+        }
+    }
+
+    /// CIRAM offset for a nametable address, or `None` when the cartridge
+    /// maps its own memory there. $3000-$3EFF mirrors $2000-$2EFF.
+    fn ciram_offset(cartridge: &dyn Mapper, addr: u16) -> Option<usize> {
+        let table = (addr >> 10) & 0x03;
+        match cartridge.nametable(table) {
+            Nametable::Ciram(page) => Some(page as usize * 0x400 + (addr & 0x03FF) as usize),
+            Nametable::Cartridge => None,
+        }
+    }
+
+    fn read(&self, cartridge: &mut dyn Mapper, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => cartridge.ppu_read(addr),
+            0x2000..=0x3EFF => match Self::ciram_offset(cartridge, addr) {
+                Some(offset) => self.vram[offset],
+                None => cartridge.nametable_read(0x2000 | (addr & 0x0FFF)),
+            },
+            _ => self.read_palette(addr),
+        }
+    }
+
+    fn write(&mut self, cartridge: &mut dyn Mapper, addr: u16, data: u8) {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => cartridge.ppu_write(addr, data),
+            0x2000..=0x3EFF => match Self::ciram_offset(cartridge, addr) {
+                Some(offset) => self.vram[offset] = data,
+                None => cartridge.nametable_write(0x2000 | (addr & 0x0FFF), data),
+            },
+            _ => self.palette_mem[Self::palette_index(addr)] = data & 0x3F,
+        }
+    }
+
+    pub fn cpu_read(&mut self, addr: u16, cartridge: &mut dyn Mapper) -> u8 {
         match addr & 0x007 {
-            0 | 1 | 3 | 5 | 6 => 0,
             2 => {
-                let result = (self.status & 0xE0) | (self.data & 0x1F);
-                self.status &= 0x7F;
+                let result = (self.status & 0xE0) | (self.open_bus & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                if (self.scanline, self.dot) == (self.region.vblank_line(), 1) {
+                    self.vblank_suppressed = true;
+                }
                 self.w = false;
-                result
+                self.open_bus = result;
+            }
+            4 => {
+                let mut result = self.oam[self.oam_addr as usize];
+                // Bits 2-4 of the sprite attribute byte don't exist
+                if self.oam_addr & 0x03 == 0x02 {
+                    result &= 0xE3;
+                }
+                self.open_bus = result;
             }
-            4 => self.oam[self.oam_addr as usize],
             7 => {
-                let addr = self.v;
-                let result = if addr < 0x3F00 {
+                let addr = self.v & 0x3FFF;
+                self.open_bus = if addr < 0x3F00 {
                     let buffered = self.data;
-                    self.data = self.read(addr);
+                    self.data = self.read(cartridge, addr);
                     buffered
                 } else {
-                    self.read(addr)
+                    // Palette reads are immediate, but the buffer is still
+                    // filled from the nametable byte "under" the palette
+                    self.data = self.read(cartridge, addr - 0x1000);
+                    (self.read_palette(addr) & 0x3F) | (self.open_bus & 0xC0)
                 };
-                self.v = self.v.wrapping_add(self.v_increment());
-                result
+                self.v = self.v.wrapping_add(self.v_increment()) & 0x7FFF;
             }
-            _ => 0,
+            // Write-only registers
+            _ => {}
         }
+        self.open_bus
     }
 
-    pub fn cpu_write(&mut self, addr: u16) {
+    pub fn cpu_write(&mut self, addr: u16, data: u8, cartridge: &mut dyn Mapper) {
+        self.open_bus = data;
         match addr & 0x007 {
-            0 => {}
+            0 => {
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | (((data & CTRL_NAMETABLE) as u16) << 10);
+            }
+            1 => self.mask = data,
+            2 => {}
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (data >> 3) as u16;
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | (((data & 0x07) as u16) << 12)
+                        | (((data & 0xF8) as u16) << 2);
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | (((data & 0x3F) as u16) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            _ => {
+                self.write(cartridge, self.v, data);
+                self.v = self.v.wrapping_add(self.v_increment()) & 0x7FFF;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::render::{DOTS_PER_LINE, POST_RENDER_LINE};
+
+    const VBLANK_LINE: u16 = 241;
+    const PRE_RENDER_LINE: u16 = 261;
+    use super::*;
+    use crate::cartridge;
+    use crate::rom::{Mirroring, Rom};
+
+    fn cartridge() -> Box<dyn Mapper> {
+        cartridge_with(0, Mirroring::Horizontal)
+    }
+
+    fn cartridge_with(mapper: u16, mirroring: Mirroring) -> Box<dyn Mapper> {
+        cartridge::from_rom(Rom {
+            prg_rom: vec![0; 0x8000],
+            mapper,
+            mirroring,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn write_vram(ppu: &mut PPU, cart: &mut dyn Mapper, addr: u16, data: &[u8]) {
+        ppu.cpu_write(6, (addr >> 8) as u8, cart);
+        ppu.cpu_write(6, addr as u8, cart);
+        for &byte in data {
+            ppu.cpu_write(7, byte, cart);
+        }
+    }
+
+    fn run_frame(ppu: &mut PPU, cart: &mut dyn Mapper) {
+        let frame = ppu.frame_count();
+        while ppu.frame_count() == frame {
+            ppu.tick(cart);
+        }
+    }
+
+    /// Tile 1 is solid color 1, tile 2 has a single color 3 pixel at its
+    /// top-left. The top-left nametable tile is tile 1.
+    fn ppu_with_tiles(cart: &mut dyn Mapper) -> PPU {
+        let mut ppu = PPU::new();
+        write_vram(&mut ppu, cart, 0x0010, &[0xFF; 8]);
+        write_vram(&mut ppu, cart, 0x0020, &[0x80]);
+        write_vram(&mut ppu, cart, 0x0028, &[0x80]);
+        write_vram(&mut ppu, cart, 0x2000, &[0x01]);
+        write_vram(&mut ppu, cart, 0x3F00, &[0x0F, 0x16]);
+        write_vram(&mut ppu, cart, 0x3F17, &[0x2A]);
+        // Point the scroll back at the top-left of the first nametable
+        write_vram(&mut ppu, cart, 0x0000, &[]);
+        ppu
+    }
+
+    fn pixel(ppu: &PPU, x: usize, y: usize) -> u8 {
+        ppu.framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_render_background() {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        ppu.cpu_write(0, 0x00, cart.as_mut());
+        ppu.cpu_write(
+            1,
+            MASK_SHOW_BACKGROUND | MASK_BACKGROUND_LEFT,
+            cart.as_mut(),
+        );
+        run_frame(&mut ppu, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        assert_eq!(pixel(&ppu, 0, 0), 0x16);
+        assert_eq!(pixel(&ppu, 7, 7), 0x16);
+        assert_eq!(pixel(&ppu, 8, 0), 0x0F);
+        assert_eq!(pixel(&ppu, 0, 8), 0x0F);
+
+        // Fine X scroll of 3 moves the tile three pixels left
+        ppu.cpu_write(5, 3, cart.as_mut());
+        ppu.cpu_write(5, 0, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        assert_eq!(pixel(&ppu, 4, 0), 0x16);
+        assert_eq!(pixel(&ppu, 5, 0), 0x0F);
+
+        // Left column clipping shows the backdrop
+        ppu.cpu_write(1, MASK_SHOW_BACKGROUND, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        assert_eq!(pixel(&ppu, 0, 0), 0x0F);
+    }
+
+    #[test]
+    fn test_render_sprites() {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        ppu.cpu_write(3, 0, cart.as_mut());
+        // Y is one less than the first line the sprite appears on
+        for byte in [19, 2, 0x01, 30, 39, 2, 0x41, 30] {
+            ppu.cpu_write(4, byte, cart.as_mut());
+        }
+        ppu.cpu_write(0, 0x00, cart.as_mut());
+        ppu.cpu_write(1, 0x1E, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+
+        assert_eq!(pixel(&ppu, 30, 20), 0x2A);
+        assert_eq!(pixel(&ppu, 31, 20), 0x0F);
+        assert_eq!(pixel(&ppu, 30, 19), 0x0F);
+        // Horizontally flipped copy
+        assert_eq!(pixel(&ppu, 37, 40), 0x2A);
+        assert_eq!(pixel(&ppu, 30, 40), 0x0F);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        ppu.cpu_write(3, 0, cart.as_mut());
+        // Behind the background at (0, 1), in front of the backdrop at (8, 1)
+        for byte in [0, 2, 0x21, 0, 0, 2, 0x21, 8] {
+            ppu.cpu_write(4, byte, cart.as_mut());
+        }
+        ppu.cpu_write(1, 0x1E, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        assert_eq!(pixel(&ppu, 0, 1), 0x16);
+        assert_eq!(pixel(&ppu, 8, 1), 0x2A);
+    }
+
+    fn run_to(ppu: &mut PPU, cart: &mut dyn Mapper, scanline: u16, dot: u16) {
+        while (ppu.scanline, ppu.dot) != (scanline, dot) {
+            ppu.tick(cart);
+        }
+    }
+
+    fn write_oam(ppu: &mut PPU, cart: &mut dyn Mapper, sprites: &[[u8; 4]]) {
+        ppu.cpu_write(3, 0, cart);
+        for byte in sprites.iter().flatten() {
+            ppu.cpu_write(4, *byte, cart);
+        }
+    }
+
+    /// Sets up sprite 0 over a solid background tile at `x` on line 1 and
+    /// returns whether the hit flag was set by the end of that line.
+    fn sprite0_hit_at(x: u8, mask: u8) -> bool {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        write_vram(&mut ppu, cart.as_mut(), 0x201F, &[0x01]);
+        write_vram(&mut ppu, cart.as_mut(), 0x0000, &[]);
+        write_oam(&mut ppu, cart.as_mut(), &[[0, 2, 0, x]]);
+        ppu.cpu_write(1, mask, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+        run_to(&mut ppu, cart.as_mut(), 2, 0);
+        ppu.status & STATUS_SPRITE0_HIT != 0
+    }
+
+    #[test]
+    fn test_sprite0_hit() {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        write_oam(&mut ppu, cart.as_mut(), &[[0, 2, 0, 4]]);
+        ppu.cpu_write(1, 0x1E, cart.as_mut());
+        run_frame(&mut ppu, cart.as_mut());
+
+        // The sprite's pixel is drawn at dot 5 of line 1
+        run_to(&mut ppu, cart.as_mut(), 1, 5);
+        assert_eq!(ppu.status & STATUS_SPRITE0_HIT, 0);
+        ppu.tick(cart.as_mut());
+        assert_ne!(ppu.status & STATUS_SPRITE0_HIT, 0);
+
+        // Stays set through vblank, cleared at dot 1 of the pre-render line
+        run_to(&mut ppu, cart.as_mut(), PRE_RENDER_LINE, 1);
+        assert_ne!(ppu.status & STATUS_SPRITE0_HIT, 0);
+        ppu.tick(cart.as_mut());
+        assert_eq!(ppu.status & STATUS_SPRITE0_HIT, 0);
+    }
+
+    #[test]
+    fn test_sprite0_hit_edges() {
+        assert!(sprite0_hit_at(0, 0x1E));
+        assert!(sprite0_hit_at(254, 0x1E));
+        assert!(!sprite0_hit_at(255, 0x1E));
+        // Clipping either layer in the left column prevents the hit there
+        assert!(!sprite0_hit_at(0, 0x1A));
+        assert!(!sprite0_hit_at(0, 0x1C));
+        assert!(sprite0_hit_at(248, 0x18));
+        // Both layers must be enabled
+        assert!(!sprite0_hit_at(0, 0x16));
+    }
+
+    /// Supplies all four nametables itself and logs every PPU read.
+    #[derive(Default)]
+    struct LoggingCartridge {
+        reads: Vec<u16>,
+    }
+
+    impl Mapper for LoggingCartridge {
+        fn cpu_read(&self, _addr: u16) -> u8 {
+            0
+        }
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+        fn ppu_read(&mut self, addr: u16) -> u8 {
+            self.reads.push(addr);
+            0
+        }
+        fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::FourScreen
+        }
+        fn nametable(&self, _table: u16) -> Nametable {
+            Nametable::Cartridge
+        }
+        fn nametable_read(&mut self, addr: u16) -> u8 {
+            self.reads.push(addr);
+            0
+        }
+    }
+
+    #[test]
+    fn test_background_fetch_timing() {
+        let mut cart = LoggingCartridge::default();
+        let mut ppu = PPU::new();
+        ppu.cpu_write(1, MASK_SHOW_BACKGROUND, &mut cart);
+        run_to(&mut ppu, &mut cart, 1, 0);
+
+        let mut nametable_dots = Vec::new();
+        for dot in 0..DOTS_PER_LINE {
+            cart.reads.clear();
+            ppu.tick(&mut cart);
+            if dot == 257 {
+                assert_eq!(cart.reads, []);
+            }
+            let nametable = |addr: &u16| (0x2000..0x3000).contains(addr) && addr & 0x03FF < 0x03C0;
+            if cart.reads.iter().any(nametable) {
+                nametable_dots.push(dot);
+            }
+        }
+        // One fetch per tile, then the two unused ones at 337 and 339
+        let expected: Vec<u16> = (1..=249).step_by(8).chain([321, 329, 337, 339]).collect();
+        assert_eq!(nametable_dots, expected);
+    }
+
+    /// Runs a frame with `sprites` in OAM and reports the overflow flag.
+    fn sprite_overflow(sprites: &[[u8; 4]]) -> bool {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        let mut oam = [[0xF0; 4]; 64];
+        oam[..sprites.len()].copy_from_slice(sprites);
+        write_oam(&mut ppu, cart.as_mut(), &oam);
+        ppu.cpu_write(1, 0x18, cart.as_mut());
+        run_to(&mut ppu, cart.as_mut(), POST_RENDER_LINE, 0);
+        ppu.status & STATUS_SPRITE_OVERFLOW != 0
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let on_line = [10, 0xF0, 0xF0, 0xF0];
+        assert!(!sprite_overflow(&[on_line; 8]));
+        assert!(sprite_overflow(&[on_line; 9]));
+
+        // After eight sprites the scan walks diagonally through OAM: sprite
+        // 8 is checked by its Y, sprite 9 by its tile number, ...
+        let mut false_positive = vec![on_line; 8];
+        false_positive.push([100, 0xF0, 0xF0, 0xF0]);
+        false_positive.push([100, 10, 0xF0, 0xF0]);
+        assert!(sprite_overflow(&false_positive));
+
+        let mut false_negative = vec![on_line; 8];
+        false_negative.push([100, 0xF0, 0xF0, 0xF0]);
+        false_negative.extend([on_line; 3]);
+        assert!(!sprite_overflow(&false_negative));
+    }
+
+    #[test]
+    fn test_sprite_overflow_timing() {
+        let mut cart = cartridge();
+        let mut ppu = ppu_with_tiles(cart.as_mut());
+        // Four sprites off the line take two dots each, eight on it take
+        // eight each, so the ninth is found at dot 65 + 8 + 64
+        let mut oam = [[0xF0; 4]; 64];
+        oam[4..13].fill([10, 0xF0, 0xF0, 0xF0]);
+        write_oam(&mut ppu, cart.as_mut(), &oam);
+        ppu.cpu_write(1, 0x18, cart.as_mut());
+
+        run_to(&mut ppu, cart.as_mut(), 10, 137);
+        assert_eq!(ppu.cpu_read(2, cart.as_mut()) & STATUS_SPRITE_OVERFLOW, 0);
+        ppu.tick(cart.as_mut());
+        assert_ne!(ppu.cpu_read(2, cart.as_mut()) & STATUS_SPRITE_OVERFLOW, 0);
+    }
+
+    /// Writes a distinct byte to each of the four nametables and reads back
+    /// what ends up in each slot.
+    fn nametable_contents(cart: &mut dyn Mapper) -> [u8; 4] {
+        let mut ppu = PPU::new();
+        for (table, value) in [(0x2000, 1), (0x2400, 2), (0x2800, 3), (0x2C00, 4)] {
+            write_vram(&mut ppu, cart, table + 0x10, &[value]);
+        }
+        [0x2010, 0x2410, 0x2810, 0x3C10].map(|addr| {
+            write_vram(&mut ppu, cart, addr, &[]);
+            ppu.cpu_read(7, cart);
+            ppu.cpu_read(7, cart)
+        })
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        let mut horizontal = cartridge_with(0, Mirroring::Horizontal);
+        assert_eq!(nametable_contents(horizontal.as_mut()), [2, 2, 4, 4]);
+        let mut vertical = cartridge_with(0, Mirroring::Vertical);
+        assert_eq!(nametable_contents(vertical.as_mut()), [3, 4, 3, 4]);
+        let mut four_screen = cartridge_with(4, Mirroring::FourScreen);
+        assert_eq!(nametable_contents(four_screen.as_mut()), [1, 2, 3, 4]);
+
+        // AxROM switches between the two one-screen layouts at runtime
+        let mut axrom = cartridge_with(7, Mirroring::Horizontal);
+        assert_eq!(nametable_contents(axrom.as_mut()), [4, 4, 4, 4]);
+        axrom.cpu_write(0x8000, 0x10);
+        let mut ppu = PPU::new();
+        write_vram(&mut ppu, axrom.as_mut(), 0x2000, &[]);
+        ppu.cpu_read(7, axrom.as_mut());
+        assert_eq!(ppu.cpu_read(7, axrom.as_mut()), 0);
+    }
+
+    #[test]
+    fn test_palette_mirroring() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+        write_vram(
+            &mut ppu,
+            cart.as_mut(),
+            0x3F10,
+            &[0x21, 0x22, 0x23, 0x24, 0x25],
+        );
+        write_vram(&mut ppu, cart.as_mut(), 0x3F18, &[0x26]);
+        assert_eq!(ppu.palette_mem[0x00], 0x21);
+        assert_eq!(ppu.palette_mem[0x11], 0x22);
+        assert_eq!(ppu.palette_mem[0x04], 0x25);
+        assert_eq!(ppu.palette_mem[0x08], 0x26);
+        assert_eq!(ppu.palette_mem[0x10], 0);
+
+        // $3F20-$3FFF mirrors the 32 palette bytes
+        write_vram(&mut ppu, cart.as_mut(), 0x3FE0, &[]);
+        assert_eq!(ppu.cpu_read(7, cart.as_mut()) & 0x3F, 0x21);
+    }
+
+    #[test]
+    fn test_scroll_and_addr_latches() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+
+        // The sequence from the nesdev "PPU scrolling" wiki page
+        ppu.cpu_write(0, 0x00, cart.as_mut());
+        ppu.cpu_read(2, cart.as_mut());
+        ppu.cpu_write(5, 0x7D, cart.as_mut());
+        assert_eq!((ppu.t, ppu.x, ppu.w), (0x000F, 5, true));
+        ppu.cpu_write(5, 0x5E, cart.as_mut());
+        assert_eq!((ppu.t, ppu.w), (0x616F, false));
+        ppu.cpu_write(6, 0x3D, cart.as_mut());
+        assert_eq!((ppu.t, ppu.w), (0x3D6F, true));
+        ppu.cpu_write(6, 0xF0, cart.as_mut());
+        assert_eq!((ppu.t, ppu.v, ppu.w), (0x3DF0, 0x3DF0, false));
+
+        ppu.cpu_write(0, 0x03, cart.as_mut());
+        assert_eq!(ppu.t, 0x3DF0 | 0x0C00);
+
+        // Reading PPUSTATUS resets the toggle between the two writes
+        ppu.cpu_write(6, 0x21, cart.as_mut());
+        ppu.cpu_read(2, cart.as_mut());
+        ppu.cpu_write(6, 0x22, cart.as_mut());
+        ppu.cpu_write(6, 0x08, cart.as_mut());
+        assert_eq!(ppu.v, 0x2208);
+    }
+
+    #[test]
+    fn test_ppudata_buffer_and_increment() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+        ppu.cpu_write(6, 0x20, cart.as_mut());
+        ppu.cpu_write(6, 0x00, cart.as_mut());
+        for value in [0x11, 0x22, 0x33] {
+            ppu.cpu_write(7, value, cart.as_mut());
+        }
+        ppu.cpu_write(6, 0x3F, cart.as_mut());
+        ppu.cpu_write(6, 0x00, cart.as_mut());
+        ppu.cpu_write(7, 0x0F, cart.as_mut());
+
+        ppu.cpu_write(6, 0x20, cart.as_mut());
+        ppu.cpu_write(6, 0x00, cart.as_mut());
+        assert_eq!(ppu.cpu_read(7, cart.as_mut()), 0x00); // stale buffer
+        assert_eq!(ppu.cpu_read(7, cart.as_mut()), 0x11);
+        assert_eq!(ppu.cpu_read(7, cart.as_mut()), 0x22);
+
+        ppu.cpu_write(0, CTRL_INCREMENT_32, cart.as_mut());
+        ppu.cpu_write(6, 0x20, cart.as_mut());
+        ppu.cpu_write(6, 0x00, cart.as_mut());
+        ppu.cpu_read(7, cart.as_mut());
+        assert_eq!(ppu.v, 0x2020);
+
+        // Palette reads skip the buffer
+        ppu.cpu_write(6, 0x3F, cart.as_mut());
+        ppu.cpu_write(6, 0x00, cart.as_mut());
+        assert_eq!(ppu.cpu_read(7, cart.as_mut()) & 0x3F, 0x0F);
+    }
+
+    #[test]
+    fn test_status_read() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+        ppu.status = STATUS_VBLANK;
+        ppu.cpu_write(5, 0x1F, cart.as_mut());
+        assert_eq!(ppu.cpu_read(2, cart.as_mut()), STATUS_VBLANK | 0x1F);
+        assert_eq!(ppu.cpu_read(2, cart.as_mut()) & STATUS_VBLANK, 0);
+        assert!(!ppu.w);
+
+        ppu.cpu_write(3, 0x02, cart.as_mut());
+        ppu.cpu_write(4, 0xFF, cart.as_mut());
+        ppu.cpu_write(3, 0x02, cart.as_mut());
+        assert_eq!(ppu.cpu_read(4, cart.as_mut()), 0xE3);
+    }
+
+    #[test]
+    fn test_vblank_and_nmi() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 1);
+        assert_eq!(ppu.status & STATUS_VBLANK, 0);
+        ppu.tick(cart.as_mut());
+        assert_ne!(ppu.status & STATUS_VBLANK, 0);
+
+        // Enabling NMI during vblank raises the line right away
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 100);
+        assert!(!ppu.nmi_line());
+        ppu.cpu_write(0, CTRL_NMI, cart.as_mut());
+        assert!(ppu.nmi_line());
+
+        run_to(&mut ppu, cart.as_mut(), PRE_RENDER_LINE, 1);
+        assert!(ppu.nmi_line());
+        ppu.tick(cart.as_mut());
+        assert!(!ppu.nmi_line());
+        assert_eq!(ppu.status & STATUS_VBLANK, 0);
+    }
+
+    #[test]
+    fn test_vblank_read_race() {
+        let mut cart = cartridge();
+        let mut ppu = PPU::new();
+        ppu.cpu_write(0, CTRL_NMI, cart.as_mut());
+
+        // Reading one dot before vblank misses the flag and suppresses it
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 1);
+        assert_eq!(ppu.cpu_read(2, cart.as_mut()) & STATUS_VBLANK, 0);
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 100);
+        assert_eq!(ppu.status & STATUS_VBLANK, 0);
+        assert!(!ppu.nmi_line());
+
+        // Reading right after it is set sees the flag, but the NMI is lost
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 2);
+        assert!(!ppu.nmi_line());
+        assert_ne!(ppu.cpu_read(2, cart.as_mut()) & STATUS_VBLANK, 0);
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 100);
+        assert!(!ppu.nmi_line());
+
+        // The next frame is unaffected
+        run_to(&mut ppu, cart.as_mut(), VBLANK_LINE, 4);
+        assert!(ppu.nmi_line());
+    }
+
+    #[test]
+    fn test_region_frame_layout() {
+        let mut cart = cartridge();
+        for (region, vblank_line, dots) in [
+            (Region::Pal, 241, 312 * 341),
+            (Region::Dendy, 291, 312 * 341),
+        ] {
+            let mut ppu = PPU::new();
+            ppu.set_region(region);
+            ppu.cpu_write(1, MASK_SHOW_BACKGROUND, cart.as_mut());
+            run_to(&mut ppu, cart.as_mut(), 250, 0);
+            assert_eq!(ppu.status & STATUS_VBLANK == 0, vblank_line > 250);
+            run_to(&mut ppu, cart.as_mut(), vblank_line, 2);
+            assert_ne!(ppu.status & STATUS_VBLANK, 0);
+
+            // No dot is skipped on odd frames
+            run_frame(&mut ppu, cart.as_mut());
+            let mut ticks = 0;
+            for _ in 0..2 {
+                let frame = ppu.frame_count();
+                while ppu.frame_count() == frame {
+                    ppu.tick(cart.as_mut());
+                    ticks += 1;
+                }
+            }
+            assert_eq!(ticks, 2 * dots);
         }
     }
 }
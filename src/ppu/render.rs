@@ -0,0 +1,386 @@
+use super::*;
+
+pub(super) const DOTS_PER_LINE: u16 = 341;
+pub(super) const POST_RENDER_LINE: u16 = 240;
+
+/// Background fetch latches and the 16-bit shift registers they feed. The
+/// high byte of each shifter is the tile being drawn, the low byte the next.
+#[derive(Default)]
+pub(super) struct Background {
+    nametable: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    shift_pattern_lo: u16,
+    shift_pattern_hi: u16,
+    shift_attribute_lo: u16,
+    shift_attribute_hi: u16,
+}
+
+/// Sprites found by evaluation for the next line, and the up to eight
+/// sprites being drawn on the current one.
+#[derive(Default)]
+pub(super) struct Sprites {
+    /// Secondary OAM.
+    secondary: [u8; 32],
+    found: usize,
+    /// Secondary OAM slot 0 holds sprite 0.
+    sprite0_found: bool,
+    /// Dot at which evaluation comes across a ninth sprite on the next line.
+    overflow_dot: Option<u16>,
+    count: usize,
+    sprite0_in_line: bool,
+    x: [u8; 8],
+    attributes: [u8; 8],
+    pattern_lo: [u8; 8],
+    pattern_hi: [u8; 8],
+}
+
+impl PPU {
+    /// Background or sprite rendering is enabled in PPUMASK.
+    pub(super) fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_SHOW_BACKGROUND | MASK_SHOW_SPRITES) != 0
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_8X16 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Advances the PPU by one dot.
+    pub fn tick(&mut self, cartridge: &mut dyn Mapper) {
+        let rendering = self.rendering_enabled();
+        let line = self.scanline;
+        let dot = self.dot;
+        let pre_render = self.region.pre_render_line();
+
+        if rendering && (line < POST_RENDER_LINE || line == pre_render) {
+            self.background_fetches(cartridge);
+            if dot == 65 && line < POST_RENDER_LINE {
+                self.evaluate_sprites();
+            }
+            if line < POST_RENDER_LINE && self.sprites.overflow_dot == Some(dot) {
+                self.status |= STATUS_SPRITE_OVERFLOW;
+            }
+            if (257..=320).contains(&dot) {
+                self.sprite_fetches(cartridge);
+            }
+        }
+        if line < POST_RENDER_LINE && (1..=256).contains(&dot) {
+            self.output_pixel();
+        }
+        if line == self.region.vblank_line() && dot == 1 {
+            if !self.vblank_suppressed {
+                self.status |= STATUS_VBLANK;
+            }
+            self.vblank_suppressed = false;
+        }
+        if line == pre_render && dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE0_HIT | STATUS_SPRITE_OVERFLOW);
+        }
+
+        self.advance_dot(rendering);
+    }
+
+    fn advance_dot(&mut self, rendering: bool) {
+        self.dot += 1;
+        // Odd frames skip the last dot of the pre-render line when rendering
+        if self.scanline == self.region.pre_render_line()
+            && self.dot == 340
+            && self.odd_frame
+            && rendering
+            && self.region.skips_odd_frame_dot()
+        {
+            self.dot = DOTS_PER_LINE;
+        }
+        if self.dot < DOTS_PER_LINE {
+            return;
+        }
+
+        self.dot = 0;
+        self.scanline += 1;
+        if self.scanline == POST_RENDER_LINE {
+            std::mem::swap(&mut self.frame, &mut self.pixels);
+            self.frame_count += 1;
+        } else if self.scanline > self.region.pre_render_line() {
+            self.scanline = 0;
+            self.odd_frame = !self.odd_frame;
+        }
+    }
+
+    fn background_fetches(&mut self, cartridge: &mut dyn Mapper) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+            // The tile fetched over the last eight dots becomes the next one
+            if (dot - 1).is_multiple_of(8) {
+                self.load_background_shifters();
+            }
+        }
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            match (dot - 1) % 8 {
+                0 => self.bg.nametable = self.read(cartridge, 0x2000 | (self.v & 0x0FFF)),
+                2 => {
+                    let v = self.v;
+                    let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let mut attribute = self.read(cartridge, addr);
+                    if v & 0x0040 != 0 {
+                        attribute >>= 4;
+                    }
+                    if v & 0x0002 != 0 {
+                        attribute >>= 2;
+                    }
+                    self.bg.attribute = attribute & 0x03;
+                }
+                4 => self.bg.pattern_lo = self.read(cartridge, self.background_pattern_addr()),
+                6 => {
+                    let addr = self.background_pattern_addr() + 8;
+                    self.bg.pattern_hi = self.read(cartridge, addr);
+                }
+                7 => self.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => self.v = (self.v & !0x041F) | (self.t & 0x041F),
+            280..=304 if self.scanline == self.region.pre_render_line() => {
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            // Unused nametable fetches at the end of the line
+            337 | 339 => {
+                self.bg.nametable = self.read(cartridge, 0x2000 | (self.v & 0x0FFF));
+            }
+            _ => {}
+        }
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        table + (self.bg.nametable as u16) * 16 + ((self.v >> 12) & 0x07)
+    }
+
+    fn shift_background(&mut self) {
+        self.bg.shift_pattern_lo <<= 1;
+        self.bg.shift_pattern_hi <<= 1;
+        self.bg.shift_attribute_lo <<= 1;
+        self.bg.shift_attribute_hi <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        let bg = &mut self.bg;
+        bg.shift_pattern_lo = (bg.shift_pattern_lo & 0xFF00) | bg.pattern_lo as u16;
+        bg.shift_pattern_hi = (bg.shift_pattern_hi & 0xFF00) | bg.pattern_hi as u16;
+        let fill = |bit: u8| if bg.attribute & bit != 0 { 0xFF } else { 0x00 };
+        bg.shift_attribute_lo = (bg.shift_attribute_lo & 0xFF00) | fill(0b01);
+        bg.shift_attribute_hi = (bg.shift_attribute_hi & 0xFF00) | fill(0b10);
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 hold attributes; wrap without switching tables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    /// Fills secondary OAM with the first eight sprites on the next line,
+    /// then keeps scanning for a ninth to set the overflow flag. Runs at dot
+    /// 65 all at once, but works out when the hardware, which spends two
+    /// dots on each sprite it checks and eight on each one it copies, would
+    /// get to the ninth sprite, so the flag can be raised at that dot.
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        let in_range = |y: u8| self.scanline.wrapping_sub(y as u16) < height;
+
+        let mut n = 0;
+        let mut found = 0;
+        let mut dot = 65;
+        while n < 64 && found < 8 {
+            let sprite = &self.oam[n * 4..n * 4 + 4];
+            if in_range(sprite[0]) {
+                self.sprites.secondary[found * 4..found * 4 + 4].copy_from_slice(sprite);
+                found += 1;
+                dot += 8;
+            } else {
+                dot += 2;
+            }
+            n += 1;
+        }
+        self.sprites.found = found;
+        self.sprites.sprite0_found = in_range(self.oam[0]);
+        self.sprites.overflow_dot = None;
+
+        // Hardware bug: once secondary OAM is full the PPU increments the
+        // byte index along with the sprite index when a sprite is out of
+        // range, so it checks tile, attribute and X bytes as if they were Y
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.oam[n * 4 + m]) {
+                self.sprites.overflow_dot = Some(dot);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 3;
+            dot += 2;
+        }
+    }
+
+    /// Dots 257-320 load the pattern data of the sprites for the next line,
+    /// two fetches per sprite slot. Empty slots still fetch tile $FF, which
+    /// mappers watching A12 rely on.
+    fn sprite_fetches(&mut self, cartridge: &mut dyn Mapper) {
+        self.oam_addr = 0;
+        let slot = ((self.dot - 257) / 8) as usize;
+        let step = (self.dot - 257) % 8;
+        if step != 4 && step != 6 {
+            return;
+        }
+
+        let in_range = slot < self.sprites.found && self.scanline != self.region.pre_render_line();
+        let (y, tile, attributes, x) = if in_range {
+            let sprite = &self.sprites.secondary[slot * 4..slot * 4 + 4];
+            (sprite[0], sprite[1], sprite[2], sprite[3])
+        } else {
+            (self.scanline as u8, 0xFF, 0, 0xFF)
+        };
+
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(y as u16) & (height - 1);
+        if attributes & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+        let (table, tile) = if height == 16 {
+            // 8x16 sprites pick the table with bit 0 of the tile number
+            let table = (tile & 0x01) as u16 * 0x1000;
+            (table, (tile & 0xFE) as u16 + (row >> 3))
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            (table, tile as u16)
+        };
+        let addr = table + tile * 16 + (row & 0x07);
+
+        if step == 4 {
+            let mut pattern = self.read(cartridge, addr);
+            if attributes & 0x40 != 0 {
+                pattern = pattern.reverse_bits();
+            }
+            self.sprites.pattern_lo[slot] = if in_range { pattern } else { 0 };
+        } else {
+            let mut pattern = self.read(cartridge, addr + 8);
+            if attributes & 0x40 != 0 {
+                pattern = pattern.reverse_bits();
+            }
+            self.sprites.pattern_hi[slot] = if in_range { pattern } else { 0 };
+            self.sprites.x[slot] = x;
+            self.sprites.attributes[slot] = attributes;
+            if slot == 7 {
+                let pre_render = self.scanline == self.region.pre_render_line();
+                self.sprites.count = if pre_render { 0 } else { self.sprites.found };
+                self.sprites.sprite0_in_line = !pre_render && self.sprites.sprite0_found;
+            }
+        }
+    }
+
+    /// Background pixel (2-bit pattern value, palette) at the current dot.
+    fn background_pixel(&self, x: u16) -> (u8, u8) {
+        let show = self.mask & MASK_SHOW_BACKGROUND != 0
+            && (x >= 8 || self.mask & MASK_BACKGROUND_LEFT != 0);
+        if !show {
+            return (0, 0);
+        }
+        let bit = 0x8000 >> self.x;
+        let bg = &self.bg;
+        let pattern =
+            ((bg.shift_pattern_hi & bit != 0) as u8) << 1 | (bg.shift_pattern_lo & bit != 0) as u8;
+        let palette = ((bg.shift_attribute_hi & bit != 0) as u8) << 1
+            | (bg.shift_attribute_lo & bit != 0) as u8;
+        (pattern, palette)
+    }
+
+    /// Front-most opaque sprite pixel as (pattern value, attributes, slot).
+    fn sprite_pixel(&self, x: u16) -> Option<(u8, u8, usize)> {
+        let show =
+            self.mask & MASK_SHOW_SPRITES != 0 && (x >= 8 || self.mask & MASK_SPRITES_LEFT != 0);
+        if !show {
+            return None;
+        }
+        let sprites = &self.sprites;
+        (0..sprites.count).find_map(|i| {
+            let offset = x.wrapping_sub(sprites.x[i] as u16);
+            if offset >= 8 {
+                return None;
+            }
+            let shift = 7 - offset;
+            let pattern = ((sprites.pattern_hi[i] >> shift) & 1) << 1
+                | ((sprites.pattern_lo[i] >> shift) & 1);
+            (pattern != 0).then_some((pattern, sprites.attributes[i], i))
+        })
+    }
+
+    fn output_pixel(&mut self) {
+        let x = self.dot - 1;
+        let color_addr = if !self.rendering_enabled() {
+            // With rendering off the backdrop shows, or the palette entry
+            // `v` points at
+            if self.v & 0x3F00 == 0x3F00 {
+                self.v
+            } else {
+                0x3F00
+            }
+        } else {
+            let (bg_pattern, bg_palette) = self.background_pixel(x);
+            let sprite = self.sprite_pixel(x);
+            // Sprite 0 always sits in slot 0, so it is the front-most sprite
+            // whenever it is opaque. The hit never happens at x=255.
+            if let Some((_, _, 0)) = sprite
+                && self.sprites.sprite0_in_line
+                && bg_pattern != 0
+                && x != 255
+            {
+                self.status |= STATUS_SPRITE0_HIT;
+            }
+            match sprite {
+                Some((pattern, attributes, _)) if bg_pattern == 0 || attributes & 0x20 == 0 => {
+                    0x3F10 | ((attributes & 0x03) << 2 | pattern) as u16
+                }
+                _ if bg_pattern == 0 => 0x3F00,
+                _ => 0x3F00 | (bg_palette << 2 | bg_pattern) as u16,
+            }
+        };
+        let index = self.scanline as usize * SCREEN_WIDTH + x as usize;
+        self.pixels[index] = self.read_palette(color_addr);
+    }
+}
@@ -0,0 +1,88 @@
+//! Console regions and the clock and frame timings that differ between them.
+
+use crate::rom::Timing;
+
+/// Console variant a game runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Famiclone with PAL frame timing but an NTSC-speed CPU.
+    Dendy,
+}
+
+impl Region {
+    /// Region for the timing in the ROM header. Multi-region games run as NTSC.
+    pub fn from_timing(timing: Timing) -> Region {
+        match timing {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    /// CPU clock rate in Hz.
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// PPU dots per CPU cycle as a `(dots, cycles)` fraction: 3 dots per
+    /// cycle, except 3.2 on PAL.
+    pub fn ppu_dots_per_cpu_cycle(self) -> (u32, u32) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline on which vblank starts. PAL adds its extra lines to vblank,
+    /// Dendy before it, so its NMI comes 50 lines later than NTSC's.
+    pub fn vblank_line(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_line(self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    /// Only the NTSC PPU shortens odd frames by one dot while rendering.
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// CPU cycles in one frame, rounded up.
+    pub fn cpu_cycles_per_frame(self) -> u64 {
+        let (dots, cycles) = self.ppu_dots_per_cpu_cycle();
+        let frame_dots = self.scanlines_per_frame() as u64 * 341;
+        (frame_dots * cycles as u64).div_ceil(dots as u64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_timings() {
+        assert_eq!(Region::Ntsc.cpu_cycles_per_frame(), 29781);
+        assert_eq!(Region::Pal.cpu_cycles_per_frame(), 33248);
+        assert_eq!(Region::Dendy.cpu_cycles_per_frame(), 35464);
+        assert_eq!(Region::from_timing(Timing::MultiRegion), Region::Ntsc);
+        assert_eq!(Region::Dendy.pre_render_line(), 311);
+    }
+}
@@ -1,34 +1,181 @@
+use crate::gamedb::{self, GameDbEntry};
+use crate::hash::{self, Crc32};
+use std::error::Error;
+use std::fmt;
+
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES" + MS-DOS EOF
 const PRG_ROM_PAGE_SIZE: usize = 16384; // 16 KB
 const CHR_ROM_PAGE_SIZE: usize = 8192; // 8 KB
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+/// Why a file could not be loaded as a cartridge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RomError {
+    /// Shorter than the 16-byte header.
+    TooShort,
+    /// Does not start with "NES\x1A".
+    BadMagic,
+    /// The header announces a trainer but the file ends inside it.
+    TruncatedTrainer,
+    TruncatedPrg {
+        expected: usize,
+        actual: usize,
+    },
+    TruncatedChr {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header declares no PRG-ROM at all.
+    NoPrgRom,
+    /// A NES 2.0 size that doesn't fit in memory, from a crafted or corrupt
+    /// header.
+    ImpossibleSize,
+    UnsupportedMapper(u16),
+}
 
-#[derive(Debug, PartialEq)]
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::TooShort => write!(f, "file is too short for an iNES header"),
+            RomError::BadMagic => write!(f, "file is not in iNES format"),
+            RomError::TruncatedTrainer => write!(f, "file ends inside the trainer"),
+            RomError::TruncatedPrg { expected, actual } => write!(
+                f,
+                "PRG-ROM is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            RomError::TruncatedChr { expected, actual } => write!(
+                f,
+                "CHR-ROM is truncated: expected {} bytes, found {}",
+                expected, actual
+            ),
+            RomError::NoPrgRom => write!(f, "header declares no PRG-ROM"),
+            RomError::ImpossibleSize => write!(f, "header declares an impossible ROM size"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper {}", mapper),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Mirroring {
     Vertical,
+    #[default]
     Horizontal,
     FourScreen,
+    /// All four nametables show the first (lower) 1 KB page of VRAM.
+    SingleScreenLower,
+    /// All four nametables show the second (upper) 1 KB page of VRAM.
+    SingleScreenUpper,
+}
+
+/// CPU/PPU timing the game was made for (NES 2.0 byte 12).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timing {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Runs on both NTSC and PAL consoles.
+    MultiRegion,
+    Dendy,
+}
+
+/// Console the cartridge plugs into (flags 7 and NES 2.0 byte 13).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    /// Vs. System arcade board with its PPU model and hardware type codes.
+    VsSystem {
+        ppu: u8,
+        hardware: u8,
+    },
+    Playchoice10,
+    /// Extended console type code from byte 13 (Famiclones, VT chips, ...).
+    Extended(u8),
 }
 
+#[derive(Default)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
-    pub mapper: u8,
+    /// 12-bit mapper number (8 bits for plain iNES).
+    pub mapper: u16,
+    /// NES 2.0 submapper, 0 for plain iNES.
+    pub submapper: u8,
     pub mirroring: Mirroring,
+    /// Battery or other non-volatile memory is present (flags 6 bit 1).
+    pub battery: bool,
+    /// Volatile PRG-RAM size in bytes.
+    pub prg_ram_size: usize,
+    /// Battery-backed PRG-RAM size in bytes.
+    pub prg_nvram_size: usize,
+    /// Volatile CHR-RAM size in bytes.
+    pub chr_ram_size: usize,
+    /// Battery-backed CHR-RAM size in bytes.
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    /// Number of miscellaneous ROMs after CHR (NES 2.0 byte 14).
+    pub misc_roms: u8,
+    /// Default expansion device code (NES 2.0 byte 15), e.g. 1 for the
+    /// standard controllers or 8 for the Zapper.
+    pub expansion_device: u8,
 }
 
-impl Rom {
-    pub fn new(raw: &[u8]) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES format".to_string());
-        }
+/// Decodes a NES 2.0 ROM size from its LSB byte and MSB nibble. An MSB of
+/// $F switches to exponent-multiplier notation: 2^E * (MM * 2 + 1). `None`
+/// if the size overflows.
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(page_size)
+    }
+}
+
+/// Decodes a NES 2.0 RAM size shift count, where 0 means no RAM.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
 
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+/// Old dumping tools wrote a signature such as "DiskDude!" over header bytes
+/// 7-15, which must be zero in plain iNES. Byte 7 then holds text instead of
+/// the mapper high nibble.
+fn has_header_garbage(raw: &[u8]) -> bool {
+    raw[7] & 0b0000_1100 == 0b0000_0100 || raw[12..16].iter().any(|&b| b != 0)
+}
 
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        Rom::new_with_db(raw, gamedb::GAME_DB)
+    }
+
+    /// `Rom::new`, fixing iNES headers from `db` instead of the built-in
+    /// database.
+    pub(crate) fn new_with_db(raw: &[u8], db: &[GameDbEntry]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TooShort);
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::BadMagic);
         }
 
+        let nes2 = raw[7] & 0b0000_1100 == 0b0000_1000;
+        let flags7 = if !nes2 && has_header_garbage(raw) {
+            eprintln!("WARNING: Ignoring garbage in header bytes 7-15 (mapper high nibble)");
+            0
+        } else {
+            raw[7]
+        };
+        let mut mapper = ((flags7 & 0b1111_0000) | (raw[6] >> 4)) as u16;
+
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
         let mirroring = match (four_screen, vertical_mirroring) {
@@ -36,21 +183,120 @@ impl Rom {
             (false, true) => Mirroring::Vertical,
             (false, false) => Mirroring::Horizontal,
         };
+        let battery = raw[6] & 0b10 != 0;
+
+        let mut console_type = match flags7 & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem {
+                ppu: 0,
+                hardware: 0,
+            },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(0),
+        };
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        let prg_rom_size;
+        let chr_rom_size;
+        let mut rom = Rom {
+            mirroring,
+            battery,
+            ..Default::default()
+        };
+
+        if nes2 {
+            mapper |= ((raw[8] & 0x0F) as u16) << 8;
+            rom.submapper = raw[8] >> 4;
+            prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE)
+                .ok_or(RomError::ImpossibleSize)?;
+            chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE)
+                .ok_or(RomError::ImpossibleSize)?;
+            rom.prg_ram_size = nes2_ram_size(raw[10] & 0x0F);
+            rom.prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+            rom.chr_ram_size = nes2_ram_size(raw[11] & 0x0F);
+            rom.chr_nvram_size = nes2_ram_size(raw[11] >> 4);
+            rom.timing = match raw[12] & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            };
+            console_type = match console_type {
+                ConsoleType::VsSystem { .. } => ConsoleType::VsSystem {
+                    ppu: raw[13] & 0x0F,
+                    hardware: raw[13] >> 4,
+                },
+                ConsoleType::Extended(_) => ConsoleType::Extended(raw[13] & 0x0F),
+                other => other,
+            };
+            rom.misc_roms = raw[14] & 0b11;
+            rom.expansion_device = raw[15] & 0b0011_1111;
+        } else {
+            prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+            chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+            // iNES has no RAM sizes: assume the usual 8 KB of PRG-RAM, kept by
+            // the battery if there is one, and 8 KB of CHR-RAM without CHR-ROM
+            if battery {
+                rom.prg_nvram_size = 0x2000;
+            } else {
+                rom.prg_ram_size = 0x2000;
+            }
+            if chr_rom_size == 0 {
+                rom.chr_ram_size = 0x2000;
+            }
+        }
+        rom.mapper = mapper;
+        rom.console_type = console_type;
+
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
 
         let skip_trainer = raw[6] & 0b100 != 0;
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        if raw.len() < prg_rom_start {
+            return Err(RomError::TruncatedTrainer);
+        }
+        let chr_rom_start = prg_rom_start
+            .checked_add(prg_rom_size)
+            .ok_or(RomError::ImpossibleSize)?;
+        let chr_rom_end = chr_rom_start
+            .checked_add(chr_rom_size)
+            .ok_or(RomError::ImpossibleSize)?;
+        if raw.len() < chr_rom_start {
+            return Err(RomError::TruncatedPrg {
+                expected: prg_rom_size,
+                actual: raw.len() - prg_rom_start,
+            });
+        }
+        if raw.len() < chr_rom_end {
+            return Err(RomError::TruncatedChr {
+                expected: chr_rom_size,
+                actual: raw.len() - chr_rom_start,
+            });
+        }
 
-        Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper,
-            mirroring,
-        })
+        rom.prg_rom = raw[prg_rom_start..chr_rom_start].to_vec();
+        rom.chr_rom = raw[chr_rom_start..chr_rom_end].to_vec();
+
+        // NES 2.0 headers are trusted, plain iNES ones are often wrong
+        if !nes2 && let Some(entry) = gamedb::lookup_in(db, &rom) {
+            gamedb::apply(&mut rom, entry);
+        }
+        Ok(rom)
+    }
+
+    /// CRC32 of PRG+CHR, the key used by the game database.
+    pub fn crc32(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&self.prg_rom);
+        crc.update(&self.chr_rom);
+        crc.finish()
+    }
+
+    /// SHA-1 of PRG+CHR.
+    pub fn sha1(&self) -> [u8; 20] {
+        hash::sha1(&[self.prg_rom.as_slice(), &self.chr_rom].concat())
     }
 }
 // AI SLOP
@@ -71,13 +317,130 @@ mod test {
 
         let mut rom_data = test_rom.clone();
         rom_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE]);
-        rom_data.extend(vec![0; 1 * CHR_ROM_PAGE_SIZE]);
+        rom_data.extend(vec![0; CHR_ROM_PAGE_SIZE]);
 
         let rom = Rom::new(&rom_data).unwrap();
 
         assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
-        assert_eq!(rom.chr_rom.len(), 1 * CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
         assert_eq!(rom.mapper, 0);
         assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut rom_data = vec![
+            0x4E, 0x45, 0x53, 0x1A, // NES\x1a
+            0x02, // 2 * 16KB PRG ROM
+            0x00, // No CHR ROM
+            0x42, // Mapper 4 low nibble, battery
+            0x09, // NES 2.0, Vs. System
+            0x41, // Submapper 4, mapper bits 8-11 = 1
+            0x00, // ROM size MSBs
+            0x70, // 8 KB PRG-NVRAM
+            0x07, // 8 KB CHR-RAM
+            0x03, // Dendy timing
+            0x21, // Vs. hardware 2, PPU 1
+            0x01, // 1 miscellaneous ROM
+            0x08, // Zapper
+        ];
+        rom_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE]);
+
+        let rom = Rom::new(&rom_data).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.mapper, 0x104);
+        assert_eq!(rom.submapper, 4);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Dendy);
+        assert_eq!(
+            rom.console_type,
+            ConsoleType::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+        assert_eq!(rom.misc_roms, 1);
+        assert_eq!(rom.expansion_device, 8);
+    }
+
+    #[test]
+    fn test_diskdude_header() {
+        let mut rom_data = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x11];
+        rom_data.extend(b"DiskDude!");
+        rom_data.extend(vec![0; PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE]);
+
+        let rom = Rom::new(&rom_data).unwrap();
+
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn test_rom_errors() {
+        assert_eq!(
+            Rom::new(&[0x4E, 0x45, 0x53]).err(),
+            Some(RomError::TooShort)
+        );
+        assert_eq!(Rom::new(&[0; 16]).err(), Some(RomError::BadMagic));
+
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x04, 0x00];
+        header.resize(HEADER_SIZE, 0);
+        let mut rom_data = header.clone();
+        rom_data.extend(vec![0; 100]);
+        assert_eq!(Rom::new(&rom_data).err(), Some(RomError::TruncatedTrainer));
+
+        rom_data.extend(vec![0; TRAINER_SIZE + PRG_ROM_PAGE_SIZE]);
+        assert_eq!(
+            Rom::new(&rom_data).err(),
+            Some(RomError::TruncatedPrg {
+                expected: 2 * PRG_ROM_PAGE_SIZE,
+                actual: PRG_ROM_PAGE_SIZE + 100,
+            })
+        );
+
+        header[6] = 0;
+        let mut rom_data = header.clone();
+        rom_data.extend(vec![0; 2 * PRG_ROM_PAGE_SIZE + 10]);
+        assert_eq!(
+            Rom::new(&rom_data).err(),
+            Some(RomError::TruncatedChr {
+                expected: CHR_ROM_PAGE_SIZE,
+                actual: 10,
+            })
+        );
+
+        header[4] = 0;
+        assert_eq!(Rom::new(&header).err(), Some(RomError::NoPrgRom));
+
+        // NES 2.0 exponent sizes past the end of the address space
+        let mut crafted = vec![0x4E, 0x45, 0x53, 0x1A, 0xFF, 0x00, 0x00, 0x08, 0x00, 0x0F];
+        crafted.resize(80, 0);
+        assert_eq!(Rom::new(&crafted).err(), Some(RomError::ImpossibleSize));
+        // 2^63 bytes each of PRG and CHR: the sizes fit, their sum doesn't
+        crafted[4] = 0xFC;
+        crafted[5] = 0xFC;
+        crafted[9] = 0xFF;
+        assert_eq!(Rom::new(&crafted).err(), Some(RomError::ImpossibleSize));
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        // 2^10 * 3 = 3 KB
+        assert_eq!(
+            nes2_rom_size(0b0010_1001, 0x0F, PRG_ROM_PAGE_SIZE),
+            Some(3 * 1024)
+        );
+        assert_eq!(
+            nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE),
+            Some(0x102 * PRG_ROM_PAGE_SIZE)
+        );
+        assert_eq!(nes2_rom_size(0xFF, 0x0F, PRG_ROM_PAGE_SIZE), None);
     }
 }
@@ -0,0 +1,141 @@
+//! Minimal writer for 16-bit mono PCM WAV files.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the RIFF header up to the start of the sample data.
+const HEADER_LEN: u32 = 44;
+
+/// Streams samples to a WAV file. The chunk sizes in the header are filled
+/// in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Fails with `InvalidInput` if the byte rate of `sample_rate` doesn't
+    /// fit in the header.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(&header(sample_rate, 0)?)?;
+        Ok(Self { out, data_len: 0 })
+    }
+
+    /// Appends samples in -1.0..=1.0; anything outside is clipped. Fails
+    /// with `InvalidInput`, writing nothing, once the data would pass the
+    /// 4 GiB RIFF size limit.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_len = u32::try_from(samples.len())
+            .ok()
+            .and_then(|len| len.checked_mul(2))
+            .and_then(|len| len.checked_add(self.data_len))
+            .filter(|&len| len <= u32::MAX - (HEADER_LEN - 8))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "WAV file would exceed 4 GiB")
+            })?;
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len = data_len;
+        Ok(())
+    }
+
+    /// Writes the final sizes into the header and returns the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        let sizes = header(0, self.data_len)?;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&sizes[4..8])?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&sizes[40..44])?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn header(sample_rate: u32, data_len: u32) -> io::Result<[u8; HEADER_LEN as usize]> {
+    const CHANNELS: u16 = 1;
+    const BITS: u16 = 16;
+    let block_align = CHANNELS * BITS / 8;
+    let byte_rate = sample_rate
+        .checked_mul(block_align as u32)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate is too high"))?;
+
+    let mut header = [0; HEADER_LEN as usize];
+    let fields: [&[u8]; 13] = [
+        b"RIFF",
+        &(HEADER_LEN - 8 + data_len).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        &1u16.to_le_bytes(), // PCM
+        &CHANNELS.to_le_bytes(),
+        &sample_rate.to_le_bytes(),
+        &byte_rate.to_le_bytes(),
+        &block_align.to_le_bytes(),
+        &BITS.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ];
+    let mut offset = 0;
+    for field in fields {
+        header[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_file() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0]).unwrap();
+        wav.write_samples(&[2.0, 0.5]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 10);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], (36u32 + 10).to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(bytes[20..24], [1, 0, 1, 0]); // PCM, mono
+        assert_eq!(bytes[24..28], 44_100u32.to_le_bytes());
+        assert_eq!(bytes[28..32], 88_200u32.to_le_bytes());
+        assert_eq!(bytes[32..36], [2, 0, 16, 0]);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 10u32.to_le_bytes());
+
+        let samples: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(samples, [0, 32767, -32767, 32767, 16384]);
+    }
+
+    #[test]
+    fn test_wav_size_limit() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+        wav.data_len = u32::MAX - 36 - 2;
+        let err = wav.write_samples(&[0.0, 0.0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        wav.write_samples(&[0.0]).unwrap();
+        assert_eq!(wav.data_len, u32::MAX - 36);
+        assert!(wav.write_samples(&[0.0]).is_err());
+
+        let err = WavWriter::new(Cursor::new(Vec::new()), u32::MAX)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}